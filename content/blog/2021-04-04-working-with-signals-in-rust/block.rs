@@ -0,0 +1,86 @@
+//! Keeping signals out of a critical section.
+//!
+//! Option 2 from `man 7 signal-safety`: if a handler needs to touch state
+//! that the main code is also changing, block the signal around the code
+//! that changes it. Anything that arrives in the meantime stays pending, and
+//! is delivered as soon as it's unblocked again.
+//!
+//! ```no_run
+//! use example::block::SignalBlockGuard;
+//! use example::signal::Signal;
+//! use example::sigset::SigSet;
+//!
+//! let guard = SignalBlockGuard::new(SigSet::from(&[Signal::INT, Signal::TERM]))?;
+//! // ... update whatever the handlers look at ...
+//! let delivered = guard.unblock()?;
+//! if !delivered.is_empty() {
+//!     println!("held back {:?} until we were done", delivered);
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::io;
+use std::marker::PhantomData;
+
+use crate::mask;
+use crate::sigset::SigSet;
+
+/// Blocks a set of signals on the current thread until dropped.
+///
+/// Guards nest: each one only unblocks the signals that it blocked itself,
+/// so a signal that an outer guard (or anyone else) had already blocked stays
+/// blocked until they're done with it too. The mask belongs to the thread, so
+/// guards can't be sent to other threads.
+///
+/// Leave `SIGRTMAX` out of the set while a [`SignalFd`](crate::signalfd::SignalFd)
+/// or [`Waiter`](crate::waiter::Waiter) might be set up: they use it to change
+/// other threads' masks, and can't reach a thread that blocks it.
+#[derive(Debug)]
+#[must_use = "the signals are unblocked as soon as the guard is dropped"]
+pub struct SignalBlockGuard {
+    added: SigSet,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl SignalBlockGuard {
+    pub fn new(signals: SigSet) -> io::Result<SignalBlockGuard> {
+        let old = mask::pthread_sigmask(libc::SIG_BLOCK, Some(signals))?;
+        Ok(SignalBlockGuard {
+            added: signals - old,
+            _not_send: PhantomData,
+        })
+    }
+
+    /// The signals this guard blocked, leaving out the ones that were
+    /// already blocked when it was made.
+    pub fn blocked(&self) -> SigSet {
+        self.added
+    }
+
+    /// Which of our signals have arrived since we blocked them.
+    pub fn pending(&self) -> io::Result<SigSet> {
+        Ok(SigSet::pending()? & self.added)
+    }
+
+    /// Unblocks the signals, reporting which of them were pending. Those have
+    /// been delivered by the time this returns.
+    pub fn unblock(mut self) -> io::Result<SigSet> {
+        let pending = self.pending();
+        let unblocked = self.restore();
+        self.added = SigSet::empty();
+        unblocked?;
+        pending
+    }
+
+    fn restore(&self) -> io::Result<()> {
+        mask::pthread_sigmask(libc::SIG_UNBLOCK, Some(self.added)).map(|_| ())
+    }
+}
+
+impl Drop for SignalBlockGuard {
+    fn drop(&mut self) {
+        if !self.added.is_empty() {
+            let _ = self.restore();
+        }
+    }
+}
@@ -0,0 +1,255 @@
+//! Cooperative cancellation, fed by signals.
+//!
+//! This is the same idea as Java's interrupted flag: nothing gets stopped
+//! from the outside, but long-running work checks in every so often and
+//! winds itself up when asked to. A token can be cloned and passed between
+//! threads; cancelling any clone cancels them all, along with any children
+//! made from them (but not their parents).
+
+use std::fmt;
+use std::io;
+use std::os::unix::io::{AsRawFd, OwnedFd};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::fd;
+use crate::signal::Signal;
+use crate::source::Source;
+
+type Callback = Box<dyn FnOnce() + Send>;
+
+#[derive(Default)]
+struct State {
+    cancelled: bool,
+    reason: Option<Signal>,
+    callbacks: Vec<Callback>,
+    children: Vec<Weak<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+impl Inner {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // Callbacks run outside the lock, so nothing can panic while holding
+        // it and leave it in a state that matters.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn cancel(&self, reason: Option<Signal>) {
+        let (callbacks, children) = {
+            let mut state = self.lock();
+            if state.cancelled {
+                return;
+            }
+            state.cancelled = true;
+            state.reason = reason;
+            (
+                std::mem::take(&mut state.callbacks),
+                std::mem::take(&mut state.children),
+            )
+        };
+        self.changed.notify_all();
+        for callback in callbacks {
+            callback();
+        }
+        for child in children.iter().filter_map(Weak::upgrade) {
+            child.cancel(reason);
+        }
+    }
+}
+
+/// Stops the thread started by [`CancellationToken::on_signal`] when dropped.
+struct Watcher {
+    stop: Arc<OwnedFd>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        fd::post(self.stop.as_raw_fd());
+        if let Some(thread) = self.thread.take() {
+            // When the watcher itself cancels the token, it's on its way
+            // out already.
+            if thread.thread().id() != thread::current().id() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+/// Waits on `signals` for as long as anyone holds the token.
+fn watch<S: Source>(token: &Weak<Inner>, signals: S, stop: &OwnedFd) {
+    loop {
+        {
+            // Only held while we look, so that dropping the last token
+            // drops it for real.
+            let inner = match token.upgrade() {
+                Some(inner) => inner,
+                None => return,
+            };
+            if inner.lock().cancelled {
+                return;
+            }
+            match signals.try_next() {
+                Ok(Some(signal)) => {
+                    // Let go of the signals before anyone can see that
+                    // we're done with them.
+                    drop(signals);
+                    return inner.cancel(Some(signal));
+                }
+                Ok(None) => {}
+                Err(_) => return,
+            }
+        }
+        if fd::wait_readable_any(&[signals.as_raw_fd(), stop.as_raw_fd()], None).is_err() {
+            return;
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+impl fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.inner.lock();
+        f.debug_struct("CancellationToken")
+            .field("cancelled", &state.cancelled)
+            .field("reason", &state.reason)
+            .finish()
+    }
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// A token that's cancelled as soon as a signal arrives on `signals`.
+    ///
+    /// `signals` is handed to a thread that waits on it. That thread goes
+    /// away, taking `signals` with it, once the token is cancelled for
+    /// whatever reason, or once every clone of it has been dropped.
+    pub fn on_signal<S>(signals: S) -> io::Result<CancellationToken>
+    where
+        S: Source + Send + 'static,
+    {
+        let token = CancellationToken::new();
+
+        // Lets the watching thread know that it's no longer needed.
+        let stop = Arc::new(fd::eventfd(0)?);
+
+        let watched = Arc::downgrade(&token.inner);
+        let thread = {
+            let stop = Arc::clone(&stop);
+            thread::Builder::new()
+                .name("cancel-on-signal".into())
+                .spawn(move || watch(&watched, signals, &stop))?
+        };
+        // Dropped when the token is cancelled, or along with the callbacks
+        // if it never is.
+        let watcher = Watcher {
+            stop,
+            thread: Some(thread),
+        };
+        token.on_cancel(move || drop(watcher));
+        Ok(token)
+    }
+
+    /// A token that's cancelled along with this one, but that can also be
+    /// cancelled by itself without affecting this one.
+    pub fn child(&self) -> CancellationToken {
+        let child = CancellationToken::new();
+        let mut state = self.inner.lock();
+        if state.cancelled {
+            let reason = state.reason;
+            drop(state);
+            child.inner.cancel(reason);
+        } else {
+            state.children.retain(|c| c.strong_count() > 0);
+            state.children.push(Arc::downgrade(&child.inner));
+        }
+        child
+    }
+
+    /// Cancels this token and all of its children. Not async-signal-safe:
+    /// don't call this from a handler.
+    pub fn cancel(&self) {
+        self.inner.cancel(None);
+    }
+
+    /// Like [`CancellationToken::cancel`], but records `signal` as the
+    /// reason, for when the signal was received some other way.
+    pub fn cancel_with(&self, signal: Signal) {
+        self.inner.cancel(Some(signal));
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.lock().cancelled
+    }
+
+    /// The signal that cancelled this token, if it was a signal that did it.
+    pub fn cancelled_by(&self) -> Option<Signal> {
+        self.inner.lock().reason
+    }
+
+    /// Blocks until the token is cancelled.
+    pub fn wait(&self) {
+        let mut state = self.inner.lock();
+        while !state.cancelled {
+            state = self
+                .inner
+                .changed
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Blocks until the token is cancelled or `timeout` passes. Returns
+    /// whether it was cancelled.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let deadline = match fd::deadline(timeout) {
+            Some(deadline) => deadline,
+            None => {
+                self.wait();
+                return true;
+            }
+        };
+        let mut state = self.inner.lock();
+        while !state.cancelled {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            state = self
+                .inner
+                .changed
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+        true
+    }
+
+    /// Runs `callback` when the token is cancelled, on whichever thread
+    /// cancels it. If it's already cancelled, `callback` runs straight away.
+    pub fn on_cancel<F>(&self, callback: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let mut state = self.inner.lock();
+        if state.cancelled {
+            drop(state);
+            callback();
+        } else {
+            state.callbacks.push(Box::new(callback));
+        }
+    }
+}
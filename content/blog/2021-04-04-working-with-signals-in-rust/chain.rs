@@ -0,0 +1,207 @@
+//! Handlers that share a signal with whoever was there first.
+//!
+//! `signal` and `sigaction` both hand back the handler they replaced, and
+//! it's tempting to throw it away. But the runtime, a crash reporter or some
+//! other library linked into the same binary may well have installed a
+//! `SIGSEGV` or `SIGINT` handler of its own, and it still expects to be
+//! called. Here we keep hold of the previous disposition, call it before or
+//! after our own handler, and put it back when we're done.
+//!
+//! A previous `SIG_IGN` is honoured by doing nothing. A previous `SIG_DFL`
+//! is honoured by doing whatever the default would have done: nothing for
+//! signals that are ignored by default, stopping the process for the ones
+//! that stop it, and otherwise resetting to `SIG_DFL` and re-raising, so
+//! that the process dies of the signal once the handlers have returned.
+
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU8, AtomicUsize, Ordering};
+
+use libc::c_int;
+
+use crate::exit;
+use crate::sigaction::{self, Action, Disposition, Error, InfoHandler};
+use crate::signal::{DefaultAction, Signal};
+
+/// When the previous handler runs, relative to ours.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    /// Call the previous handler, then ours.
+    PreviousFirst,
+    /// Call ours, then the previous handler.
+    PreviousLast,
+    /// Keep the previous handler so it can be put back, but don't call it.
+    Replace,
+}
+
+impl Order {
+    const fn to_raw(self) -> u8 {
+        match self {
+            Order::PreviousFirst => 0,
+            Order::PreviousLast => 1,
+            Order::Replace => 2,
+        }
+    }
+}
+
+/// One of these per signal number. A handler is a `sa_sigaction` value and
+/// the `sa_flags` it was installed with, which say how to call it.
+struct Slot {
+    claimed: AtomicBool,
+    order: AtomicU8,
+    ours: AtomicUsize,
+    ours_flags: AtomicI32,
+    previous: AtomicUsize,
+    previous_flags: AtomicI32,
+}
+
+impl Slot {
+    const fn new() -> Slot {
+        Slot {
+            claimed: AtomicBool::new(false),
+            order: AtomicU8::new(0),
+            ours: AtomicUsize::new(libc::SIG_DFL),
+            ours_flags: AtomicI32::new(0),
+            previous: AtomicUsize::new(libc::SIG_DFL),
+            previous_flags: AtomicI32::new(0),
+        }
+    }
+
+    fn set_previous(&self, previous: &Disposition) {
+        self.previous_flags
+            .store(previous.raw.sa_flags, Ordering::Release);
+        self.previous
+            .store(previous.raw.sa_sigaction, Ordering::Release);
+    }
+}
+
+static SLOTS: [Slot; 65] = [const { Slot::new() }; 65];
+
+/// Calls a handler the way the kernel would have, or does what `SIG_DFL` or
+/// `SIG_IGN` would have done in its place.
+unsafe fn invoke(
+    handler: libc::sighandler_t,
+    flags: c_int,
+    signo: c_int,
+    info: *mut libc::siginfo_t,
+    context: *mut libc::c_void,
+) {
+    match handler {
+        libc::SIG_IGN => {}
+        libc::SIG_DFL => match Signal::from_raw(signo).map(Signal::default_action) {
+            Some(DefaultAction::Ignore) | Some(DefaultAction::Continue) | None => {}
+            Some(DefaultAction::Stop) => {
+                libc::raise(libc::SIGSTOP);
+            }
+            Some(DefaultAction::Terminate) | Some(DefaultAction::Core) => {
+                exit::reset_and_raise(signo);
+            }
+        },
+        h if flags & libc::SA_SIGINFO != 0 => {
+            std::mem::transmute::<libc::sighandler_t, InfoHandler>(h)(signo, info, context)
+        }
+        h => std::mem::transmute::<libc::sighandler_t, extern "C" fn(c_int)>(h)(signo),
+    }
+}
+
+extern "C" fn on_signal(signo: c_int, info: *mut libc::siginfo_t, context: *mut libc::c_void) {
+    let slot = match SLOTS.get(signo as usize) {
+        Some(slot) => slot,
+        None => return,
+    };
+    let ours = (
+        slot.ours.load(Ordering::Acquire),
+        slot.ours_flags.load(Ordering::Acquire),
+    );
+    let previous = (
+        slot.previous.load(Ordering::Acquire),
+        slot.previous_flags.load(Ordering::Acquire),
+    );
+    let order = slot.order.load(Ordering::Acquire);
+    unsafe {
+        if order == Order::PreviousFirst.to_raw() {
+            invoke(previous.0, previous.1, signo, info, context);
+        }
+        invoke(ours.0, ours.1, signo, info, context);
+        if order == Order::PreviousLast.to_raw() {
+            invoke(previous.0, previous.1, signo, info, context);
+        }
+    }
+}
+
+/// Our handler, chained in front of (or behind) whatever was there before.
+/// Dropping this puts the previous disposition back.
+#[derive(Debug)]
+#[must_use = "the handler is uninstalled as soon as this is dropped"]
+pub struct Chained {
+    signal: Signal,
+    previous: Disposition,
+}
+
+impl Chained {
+    pub fn signal(&self) -> Signal {
+        self.signal
+    }
+
+    /// The disposition we're chained to, and which will be restored.
+    pub fn previous(&self) -> &Disposition {
+        &self.previous
+    }
+}
+
+impl Drop for Chained {
+    fn drop(&mut self) {
+        let _ = sigaction::restore(self.signal, &self.previous);
+        SLOTS[self.signal.as_raw() as usize]
+            .claimed
+            .store(false, Ordering::Release);
+    }
+}
+
+/// Installs `action` for `signal`, keeping the previous disposition and
+/// calling it in the given `order`.
+///
+/// The flags and mask come from `action`. Only one chained handler can be
+/// installed per signal at a time; to stack more, install a chained handler
+/// on top of one that was installed some other way.
+///
+/// # Safety
+///
+/// As for [`sigaction::install`]: our handler must be async-signal-safe.
+/// The previous handler is called from signal context too, but that's no
+/// different from what would have happened if we'd never been there.
+pub unsafe fn install(signal: Signal, action: &Action, order: Order) -> Result<Chained, Error> {
+    if !signal.is_catchable() {
+        return Err(Error::Uncatchable(signal));
+    }
+    let slot = &SLOTS[signal.as_raw() as usize];
+    if slot
+        .claimed
+        .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+        .is_err()
+    {
+        return Err(Error::Registered(signal));
+    }
+
+    let mut raw = action.to_raw();
+    slot.ours_flags.store(raw.sa_flags, Ordering::Release);
+    slot.ours.store(raw.sa_sigaction, Ordering::Release);
+    slot.order.store(order.to_raw(), Ordering::Release);
+    raw.sa_sigaction = on_signal as *const () as libc::sighandler_t;
+    raw.sa_flags |= libc::SA_SIGINFO;
+
+    // The signal could arrive between installing our handler and finding out
+    // what it replaced, so look first; then make sure nothing changed.
+    let installed = sigaction::current(signal).and_then(|current| {
+        slot.set_previous(&current);
+        sigaction::sigaction(signal, Some(&raw))
+    });
+    match installed {
+        Ok(previous) => {
+            slot.set_previous(&previous);
+            Ok(Chained { signal, previous })
+        }
+        Err(e) => {
+            slot.claimed.store(false, Ordering::Release);
+            Err(e)
+        }
+    }
+}
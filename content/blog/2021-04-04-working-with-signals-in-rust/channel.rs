@@ -0,0 +1,203 @@
+//! An in-process channel that can be waited on alongside signals.
+//!
+//! `std::sync::mpsc` can only be waited on by blocking in `recv`, which
+//! doesn't help when we also want to hear about a `SIGINT`. This is the same
+//! channel with an `eventfd` next to it that counts the messages waiting, so
+//! the receiving end can go into a [`Select`](crate::select::Select), a
+//! [`Reactor`](crate::reactor::Reactor), or a plain `poll`.
+//!
+//! As with any fd, readiness is a hint rather than a promise: a message can
+//! be received a moment before the sender has counted it, leaving the fd
+//! readable for a message that's already gone. So `try_recv` can come back
+//! empty just after the fd said otherwise.
+
+use std::cell::Cell;
+use std::io;
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
+use std::sync::mpsc::{self, RecvError, SendError, TryRecvError};
+use std::sync::Arc;
+
+use crate::fd;
+
+#[derive(Debug)]
+struct Shared {
+    /// Counts the messages in the channel, plus one once every sender has
+    /// gone, so that a receiver waiting on it finds out.
+    ready: OwnedFd,
+}
+
+impl Shared {
+    fn post(&self) {
+        fd::post(self.ready.as_raw_fd());
+    }
+
+    /// Returns false if there was nothing to take.
+    fn take(&self) -> bool {
+        matches!(fd::read_counter(self.ready.as_raw_fd()), Ok(n) if n > 0)
+    }
+}
+
+/// Counts the live senders, and lets the receiver know when the last one
+/// goes.
+#[derive(Debug)]
+struct Senders(Arc<Shared>);
+
+impl Drop for Senders {
+    fn drop(&mut self) {
+        self.0.post();
+    }
+}
+
+#[derive(Debug)]
+pub struct Sender<T> {
+    inner: mpsc::Sender<T>,
+    senders: Arc<Senders>,
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        Sender {
+            inner: self.inner.clone(),
+            senders: Arc::clone(&self.senders),
+        }
+    }
+}
+
+impl<T> Sender<T> {
+    pub fn send(&self, message: T) -> Result<(), SendError<T>> {
+        self.inner.send(message)?;
+        self.senders.0.post();
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct Receiver<T> {
+    inner: mpsc::Receiver<T>,
+    shared: Arc<Shared>,
+    /// Messages we've received before the sender got round to counting
+    /// them. The counts are taken back as they turn up.
+    owed: Cell<u64>,
+}
+
+impl<T> Receiver<T> {
+    /// Takes the next message if there is one, without blocking.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.settle();
+        let message = self.inner.try_recv()?;
+        self.took();
+        Ok(message)
+    }
+
+    /// Blocks until there's a message, or every sender has gone.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.settle();
+        let message = self.inner.recv()?;
+        self.took();
+        Ok(message)
+    }
+
+    fn took(&self) {
+        if !self.shared.take() {
+            self.owed.set(self.owed.get() + 1);
+        }
+    }
+
+    fn settle(&self) {
+        while self.owed.get() > 0 && self.shared.take() {
+            self.owed.set(self.owed.get() - 1);
+        }
+    }
+}
+
+impl<T> AsRawFd for Receiver<T> {
+    /// Readable whenever there's a message waiting, or once there never will
+    /// be.
+    fn as_raw_fd(&self) -> RawFd {
+        self.shared.ready.as_raw_fd()
+    }
+}
+
+/// An unbounded channel, like `std::sync::mpsc::channel`, that costs one fd.
+pub fn channel<T>() -> io::Result<(Sender<T>, Receiver<T>)> {
+    let shared = Arc::new(Shared {
+        ready: fd::eventfd(libc::EFD_SEMAPHORE)?,
+    });
+    let (tx, rx) = mpsc::channel();
+    let sender = Sender {
+        inner: tx,
+        senders: Arc::new(Senders(Arc::clone(&shared))),
+    };
+    let receiver = Receiver {
+        inner: rx,
+        shared,
+        owed: Cell::new(0),
+    };
+    Ok((sender, receiver))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::select::Select;
+
+    fn readable<T>(receiver: &Receiver<T>) -> bool {
+        let mut pollfd = libc::pollfd {
+            fd: receiver.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        unsafe { libc::poll(&mut pollfd, 1, 0) == 1 }
+    }
+
+    #[test]
+    fn counts_follow_the_messages() {
+        let (tx, rx) = channel().unwrap();
+        assert!(!readable(&rx));
+        for i in 0..3 {
+            tx.send(i).unwrap();
+        }
+        for i in 0..3 {
+            assert!(readable(&rx));
+            assert_eq!(rx.try_recv(), Ok(i));
+        }
+        assert!(!readable(&rx));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        assert!(!readable(&rx));
+    }
+
+    #[test]
+    fn try_recv_after_disconnect() {
+        let (tx, rx) = channel().unwrap();
+        tx.send("last").unwrap();
+        drop(tx);
+        assert_eq!(rx.try_recv(), Ok("last"));
+        for _ in 0..2 {
+            assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+            // Still readable, so that anyone waiting finds out.
+            assert!(readable(&rx));
+        }
+        assert_eq!(rx.recv(), Err(RecvError));
+    }
+
+    #[test]
+    fn select_wakes_when_the_sender_goes() {
+        let (tx, rx) = channel::<()>().unwrap();
+        let sender = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            drop(tx);
+        });
+
+        let mut select = Select::new();
+        let timeout = select.timeout(Duration::from_secs(5));
+        let received = select.recv(&rx);
+        assert_eq!(select.wait().unwrap(), received);
+        assert_ne!(received, timeout);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+        sender.join().unwrap();
+    }
+}
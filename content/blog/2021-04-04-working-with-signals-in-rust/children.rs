@@ -0,0 +1,179 @@
+//! Reaping child processes as they exit.
+//!
+//! A child that has exited hangs around as a zombie until its parent waits
+//! for it, and the kernel tells the parent that it's time with a `SIGCHLD`.
+//! Like any standard signal, `SIGCHLD` coalesces: ten children exiting at
+//! once may well arrive as a single signal. So the signal can only ever mean
+//! "at least one child has exited", and the [`Reaper`] answers it by calling
+//! `waitid(WNOHANG)` until there's nobody left to collect.
+//!
+//! The signal comes in through a [self-pipe](crate::pipe), so the reaping
+//! happens in ordinary code, and the pipe's fd can go into a
+//! [`Select`](crate::select::Select) or a [`Reactor`](crate::reactor::Reactor)
+//! like any other.
+//!
+//! A reaper collects _every_ child of the process, not just the ones it was
+//! told about. Anything else waiting on a particular pid, such as
+//! `std::process::Child::wait`, will find it already gone (`ECHILD`), so a
+//! process with a reaper should leave the waiting to it.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use libc::pid_t;
+
+use crate::fd;
+use crate::pipe::{self, Receiver};
+use crate::sigaction::Error;
+use crate::signal::Signal;
+
+/// How a child came to an end.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Status {
+    /// It exited by itself, with this status.
+    Exited(i32),
+    /// It was killed by a signal.
+    Killed(Signal),
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::Exited(code) => write!(f, "exited with status {}", code),
+            Status::Killed(signal) => write!(f, "killed by {}", signal),
+        }
+    }
+}
+
+/// A child that has exited and been reaped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ChildExited {
+    pub pid: pid_t,
+    pub status: Status,
+    /// Whether the signal that killed it left a core dump behind.
+    pub core_dumped: bool,
+}
+
+impl fmt::Display for ChildExited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "child {} {}", self.pid, self.status)?;
+        if self.core_dumped {
+            write!(f, " (core dumped)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Listens for `SIGCHLD` and reaps children as they exit. Dropping it puts
+/// the previous `SIGCHLD` handler back.
+#[derive(Debug)]
+pub struct Reaper {
+    signals: Receiver<Signal>,
+    /// Children we've reaped but not yet handed out.
+    exited: RefCell<VecDeque<ChildExited>>,
+}
+
+impl Reaper {
+    /// Starts listening for `SIGCHLD`. Children that exited before this are
+    /// picked up too, on the first call to [`Reaper::try_recv`].
+    ///
+    /// Only one reaper can be listening at a time; a second gives
+    /// [`Error::Registered`].
+    pub fn new() -> Result<Reaper, Error> {
+        Ok(Reaper {
+            signals: pipe::channel(&[Signal::CHLD])?,
+            exited: RefCell::new(VecDeque::new()),
+        })
+    }
+
+    /// Returns the next child to have exited, if there is one, without
+    /// blocking.
+    pub fn try_recv(&self) -> io::Result<Option<ChildExited>> {
+        if let Some(child) = self.exited.borrow_mut().pop_front() {
+            return Ok(Some(child));
+        }
+        self.reap()?;
+        Ok(self.exited.borrow_mut().pop_front())
+    }
+
+    /// Blocks until a child exits. Returns `None` once there are no children
+    /// left to wait for.
+    pub fn recv(&self) -> io::Result<Option<ChildExited>> {
+        if let Some(child) = self.exited.borrow_mut().pop_front() {
+            return Ok(Some(child));
+        }
+        loop {
+            let running = self.reap()?;
+            if let Some(child) = self.exited.borrow_mut().pop_front() {
+                return Ok(Some(child));
+            }
+            if !running {
+                return Ok(None);
+            }
+            fd::wait_readable(self.signals.as_raw_fd(), None)?;
+        }
+    }
+
+    /// Collects every child that has exited so far. Returns whether there
+    /// are any still running.
+    fn reap(&self) -> io::Result<bool> {
+        // Empty the pipe first: a child that exits after this sends another
+        // SIGCHLD, so the fd will be readable again for anything we miss.
+        while self.signals.try_recv()?.is_some() {}
+
+        let mut exited = self.exited.borrow_mut();
+        loop {
+            let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+            let waited =
+                unsafe { libc::waitid(libc::P_ALL, 0, &mut info, libc::WEXITED | libc::WNOHANG) };
+            if waited < 0 {
+                let e = io::Error::last_os_error();
+                match e.raw_os_error() {
+                    Some(libc::EINTR) => continue,
+                    Some(libc::ECHILD) => return Ok(false),
+                    _ => return Err(e),
+                }
+            }
+            // With WNOHANG, a zeroed si_pid means there are children, but
+            // none of them have exited.
+            let pid = unsafe { info.si_pid() };
+            if pid == 0 {
+                return Ok(true);
+            }
+            exited.push_back(decode(pid, &info));
+        }
+    }
+}
+
+fn decode(pid: pid_t, info: &libc::siginfo_t) -> ChildExited {
+    let status = unsafe { info.si_status() };
+    match info.si_code {
+        libc::CLD_EXITED => ChildExited {
+            pid,
+            status: Status::Exited(status),
+            core_dumped: false,
+        },
+        code => ChildExited {
+            pid,
+            // si_status holds the signal for CLD_KILLED and CLD_DUMPED, the
+            // only other codes WEXITED asks for.
+            status: Status::Killed(
+                Signal::from_raw(status).expect("the kernel only kills with real signals"),
+            ),
+            core_dumped: code == libc::CLD_DUMPED,
+        },
+    }
+}
+
+impl AsRawFd for Reaper {
+    /// Readable when `SIGCHLD` has arrived. It's non-blocking, so use
+    /// [`Reaper::try_recv`] once it's readable, and keep going until it
+    /// returns `None`: one signal can stand for several children.
+    fn as_raw_fd(&self) -> RawFd {
+        self.signals.as_raw_fd()
+    }
+}
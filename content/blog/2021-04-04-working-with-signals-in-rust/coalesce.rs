@@ -0,0 +1,85 @@
+//! Showing signal coalescing in action.
+//!
+//! We block a signal, have a child process send it to us a bunch of times,
+//! and then unblock it and count how many times the handler actually runs.
+//! For a standard signal the answer is once, however many were sent: the
+//! kernel only remembers _whether_ each standard signal is pending, not how
+//! many times. Real-time signals are queued, so every one gets through (up to
+//! `RLIMIT_SIGPENDING`).
+
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use libc::c_int;
+
+use crate::block::SignalBlockGuard;
+use crate::eintr::{self, Mode};
+use crate::sigaction::{self, Action, Error, Handler};
+use crate::signal::Signal;
+use crate::sigset::SigSet;
+
+static HANDLED: AtomicUsize = AtomicUsize::new(0);
+
+/// The handler counter is shared, so only one measurement can run at once.
+static MEASURING: Mutex<()> = Mutex::new(());
+
+extern "C" fn count(_signo: c_int) {
+    HANDLED.fetch_add(1, Ordering::Relaxed);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Measurement {
+    pub signal: Signal,
+    pub sent: usize,
+    pub handled: usize,
+}
+
+impl Measurement {
+    pub fn lost(&self) -> usize {
+        self.sent.saturating_sub(self.handled)
+    }
+}
+
+/// Sends `signal` to ourselves `sends` times from a child process while it's
+/// blocked, then counts how many times the handler runs once it isn't.
+pub fn measure(signal: Signal, sends: usize) -> Result<Measurement, Error> {
+    let _measuring = MEASURING.lock().unwrap_or_else(|e| e.into_inner());
+    HANDLED.store(0, Ordering::Relaxed);
+
+    let previous = unsafe { sigaction::install(signal, &Action::new(Handler::Function(count))) }?;
+    let result = block_and_send(signal, sends);
+    sigaction::restore(signal, &previous)?;
+    result?;
+
+    Ok(Measurement {
+        signal,
+        sent: sends,
+        handled: HANDLED.load(Ordering::Relaxed),
+    })
+}
+
+fn block_and_send(signal: Signal, sends: usize) -> io::Result<()> {
+    let blocked = SignalBlockGuard::new(SigSet::from(signal))?;
+
+    let parent = unsafe { libc::getpid() };
+    let child = unsafe { libc::fork() };
+    if child == 0 {
+        // Only async-signal-safe calls between fork and _exit.
+        for _ in 0..sends {
+            unsafe { libc::kill(parent, signal.as_raw()) };
+        }
+        unsafe { libc::_exit(0) };
+    }
+    let result = if child < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        eintr::waitpid(child, 0, Mode::Restart)
+            .map(drop)
+            .map_err(io::Error::from)
+    };
+
+    // Everything that's pending gets delivered as we unblock.
+    blocked.unblock()?;
+    result
+}
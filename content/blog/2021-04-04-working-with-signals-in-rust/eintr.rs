@@ -0,0 +1,390 @@
+//! Blocking syscalls that know what to do about `EINTR`.
+//!
+//! When a handler runs while a thread is blocked in `read`, `recv`, `accept`,
+//! `nanosleep` and the like, the call can fail with `EINTR` - always, for
+//! some calls, and otherwise whenever the handler wasn't installed with
+//! `SA_RESTART`. Each caller then has to decide whether to go round again
+//! (taking care not to restart a timeout from scratch every time) or to stop
+//! and see why it was interrupted. Here that decision is a [`Mode`], made
+//! once per call:
+//!
+//! * [`Mode::Restart`] retries until the call completes or the timeout runs
+//!   out, measuring the timeout from the first attempt;
+//! * [`Mode::Surface`] returns [`Error::Interrupted`] straight away, along
+//!   with whether the given [`CancellationToken`] has been cancelled and
+//!   whether the thread was [interrupted](crate::interrupt).
+//!
+//! ```no_run
+//! use std::os::unix::io::AsRawFd;
+//! use std::time::Duration;
+//!
+//! use example::cancel::CancellationToken;
+//! use example::eintr::{self, Mode};
+//!
+//! # fn run(socket: std::net::TcpStream, token: CancellationToken) -> Result<(), eintr::Error> {
+//! let mut buf = [0; 512];
+//! let timeout = Some(Duration::from_secs(10));
+//! match eintr::recv(socket.as_raw_fd(), &mut buf, 0, timeout, Mode::Surface(Some(&token))) {
+//!     Err(eintr::Error::Interrupted(why)) if why.cancelled => return Ok(()),
+//!     result => println!("received {} bytes", result?),
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+use std::io;
+use std::mem;
+use std::net::SocketAddr;
+use std::os::unix::io::{FromRawFd, OwnedFd, RawFd};
+use std::time::{Duration, Instant};
+
+use libc::{c_int, pid_t};
+
+use crate::cancel::CancellationToken;
+use crate::fd;
+use crate::interrupt;
+use crate::signal::Signal;
+
+/// What to do when a call fails with `EINTR`.
+#[derive(Clone, Copy, Debug)]
+pub enum Mode<'a> {
+    /// Try again, with whatever's left of the timeout.
+    Restart,
+    /// Give up, and say whether `token` (if there is one) has been cancelled.
+    Surface(Option<&'a CancellationToken>),
+}
+
+/// What was going on when a call was interrupted in [`Mode::Surface`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Interrupted {
+    /// Whether the token had been cancelled.
+    pub cancelled: bool,
+    /// The signal that cancelled it, if it was a signal.
+    pub cancelled_by: Option<Signal>,
+    /// Whether the calling thread had been interrupted through an
+    /// [`InterruptHandle`](crate::interrupt::InterruptHandle). Reporting it
+    /// here clears the thread's flag.
+    pub thread_interrupted: bool,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// A signal arrived, and we were asked not to carry on regardless.
+    Interrupted(Interrupted),
+    /// Anything else, including `TimedOut` once the timeout runs out.
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Interrupted(Interrupted {
+                cancelled_by: Some(signal),
+                ..
+            }) => write!(f, "interrupted, and cancelled by {}", signal),
+            Error::Interrupted(Interrupted {
+                cancelled: true, ..
+            }) => f.write_str("interrupted, and cancelled"),
+            Error::Interrupted(_) => f.write_str("interrupted by a signal"),
+            Error::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Interrupted(_) => None,
+            Error::Io(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> io::Error {
+        match e {
+            Error::Interrupted(_) => io::Error::new(io::ErrorKind::Interrupted, e),
+            Error::Io(e) => e,
+        }
+    }
+}
+
+/// Decides whether to go round again after `EINTR`.
+fn on_interrupt(mode: Mode<'_>) -> Result<(), Error> {
+    match mode {
+        Mode::Restart => Ok(()),
+        Mode::Surface(token) => Err(Error::Interrupted(Interrupted {
+            cancelled: token.is_some_and(CancellationToken::is_cancelled),
+            cancelled_by: token.and_then(CancellationToken::cancelled_by),
+            thread_interrupted: interrupt::interrupted(),
+        })),
+    }
+}
+
+fn timed_out() -> Error {
+    io::Error::from(io::ErrorKind::TimedOut).into()
+}
+
+/// Waits for `events` on `fd`, or for `deadline` to pass. Without a
+/// deadline, waits as long as it takes.
+fn wait(fd: RawFd, events: i16, deadline: Option<Instant>, mode: Mode<'_>) -> Result<(), Error> {
+    loop {
+        if fd::expired(deadline) {
+            return Err(timed_out());
+        }
+        let mut pollfd = libc::pollfd {
+            fd,
+            events,
+            revents: 0,
+        };
+        match unsafe { libc::poll(&mut pollfd, 1, fd::poll_timeout(fd::remaining(deadline))) } {
+            0 => return Err(timed_out()),
+            n if n > 0 => return Ok(()),
+            _ => {
+                let e = io::Error::last_os_error();
+                if e.kind() != io::ErrorKind::Interrupted {
+                    return Err(e.into());
+                }
+                on_interrupt(mode)?;
+            }
+        }
+    }
+}
+
+/// Runs `call` until it succeeds, fails for some reason other than `EINTR`,
+/// or `mode` says to stop. With a timeout, `fd` is polled for `events` first,
+/// so that the call itself shouldn't block.
+fn retry<F>(
+    fd: RawFd,
+    events: i16,
+    timeout: Option<Duration>,
+    mode: Mode<'_>,
+    mut call: F,
+) -> Result<isize, Error>
+where
+    F: FnMut() -> isize,
+{
+    // A timeout too long to have a deadline still means polling first.
+    let deadline = timeout.and_then(fd::deadline);
+    loop {
+        if timeout.is_some() {
+            wait(fd, events, deadline, mode)?;
+        }
+        let n = call();
+        if n >= 0 {
+            return Ok(n);
+        }
+        let e = io::Error::last_os_error();
+        match e.kind() {
+            io::ErrorKind::Interrupted => on_interrupt(mode)?,
+            // Someone else got there first; wait for the next time.
+            io::ErrorKind::WouldBlock if timeout.is_some() => {}
+            _ => return Err(e.into()),
+        }
+    }
+}
+
+pub fn read(
+    fd: RawFd,
+    buf: &mut [u8],
+    timeout: Option<Duration>,
+    mode: Mode<'_>,
+) -> Result<usize, Error> {
+    retry(fd, libc::POLLIN, timeout, mode, || unsafe {
+        libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len())
+    })
+    .map(|n| n as usize)
+}
+
+pub fn write(
+    fd: RawFd,
+    buf: &[u8],
+    timeout: Option<Duration>,
+    mode: Mode<'_>,
+) -> Result<usize, Error> {
+    retry(fd, libc::POLLOUT, timeout, mode, || unsafe {
+        libc::write(fd, buf.as_ptr() as *const libc::c_void, buf.len())
+    })
+    .map(|n| n as usize)
+}
+
+pub fn recv(
+    fd: RawFd,
+    buf: &mut [u8],
+    flags: c_int,
+    timeout: Option<Duration>,
+    mode: Mode<'_>,
+) -> Result<usize, Error> {
+    retry(fd, libc::POLLIN, timeout, mode, || unsafe {
+        libc::recv(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), flags)
+    })
+    .map(|n| n as usize)
+}
+
+pub fn send(
+    fd: RawFd,
+    buf: &[u8],
+    flags: c_int,
+    timeout: Option<Duration>,
+    mode: Mode<'_>,
+) -> Result<usize, Error> {
+    retry(fd, libc::POLLOUT, timeout, mode, || unsafe {
+        libc::send(fd, buf.as_ptr() as *const libc::c_void, buf.len(), flags)
+    })
+    .map(|n| n as usize)
+}
+
+/// Accepts a connection on a listening socket. The new socket is
+/// close-on-exec.
+pub fn accept(fd: RawFd, timeout: Option<Duration>, mode: Mode<'_>) -> Result<OwnedFd, Error> {
+    let n = retry(fd, libc::POLLIN, timeout, mode, || unsafe {
+        libc::accept4(
+            fd,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            libc::SOCK_CLOEXEC,
+        ) as isize
+    })?;
+    Ok(unsafe { OwnedFd::from_raw_fd(n as RawFd) })
+}
+
+fn to_sockaddr(addr: &SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let len = match addr {
+        SocketAddr::V4(v4) => {
+            let sin = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = v4.port().to_be();
+            sin.sin_addr.s_addr = u32::from_ne_bytes(v4.ip().octets());
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(v6) => {
+            let sin6 = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = v6.port().to_be();
+            sin6.sin6_flowinfo = v6.flowinfo();
+            sin6.sin6_addr.s6_addr = v6.ip().octets();
+            sin6.sin6_scope_id = v6.scope_id();
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    (storage, len as libc::socklen_t)
+}
+
+fn socket_error(fd: RawFd) -> Result<(), Error> {
+    let mut error: c_int = 0;
+    let mut len = mem::size_of::<c_int>() as libc::socklen_t;
+    let ok = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_ERROR,
+            &mut error as *mut c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ok != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    match error {
+        0 => Ok(()),
+        e => Err(io::Error::from_raw_os_error(e).into()),
+    }
+}
+
+/// Connects a socket to `addr`.
+///
+/// An interrupted `connect` can't simply be called again: the connection
+/// carries on in the background, and a second attempt fails with `EALREADY`.
+/// So after `EINTR` (in [`Mode::Restart`]) we wait for the socket to become
+/// writable and then ask it how things went. With a timeout, the socket is
+/// made non-blocking while we connect.
+pub fn connect(
+    fd: RawFd,
+    addr: &SocketAddr,
+    timeout: Option<Duration>,
+    mode: Mode<'_>,
+) -> Result<(), Error> {
+    let deadline = timeout.and_then(fd::deadline);
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let set_nonblocking = timeout.is_some() && flags & libc::O_NONBLOCK == 0;
+    if set_nonblocking && unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    let (storage, len) = to_sockaddr(addr);
+    let result =
+        match unsafe { libc::connect(fd, &storage as *const _ as *const libc::sockaddr, len) } {
+            0 => Ok(()),
+            _ => finish_connect(fd, io::Error::last_os_error(), deadline, mode),
+        };
+
+    if set_nonblocking {
+        unsafe { libc::fcntl(fd, libc::F_SETFL, flags) };
+    }
+    result
+}
+
+/// Waits for a `connect` that didn't complete straight away.
+fn finish_connect(
+    fd: RawFd,
+    e: io::Error,
+    deadline: Option<Instant>,
+    mode: Mode<'_>,
+) -> Result<(), Error> {
+    match e.raw_os_error() {
+        Some(libc::EINTR) => on_interrupt(mode)?,
+        Some(libc::EINPROGRESS) => {}
+        _ => return Err(e.into()),
+    }
+    wait(fd, libc::POLLOUT, deadline, mode)?;
+    socket_error(fd)
+}
+
+/// Waits for a child to change state. Returns its pid and raw status, or a
+/// pid of 0 if `options` included `WNOHANG` and nothing had changed.
+pub fn waitpid(pid: pid_t, options: c_int, mode: Mode<'_>) -> Result<(pid_t, c_int), Error> {
+    loop {
+        let mut status = 0;
+        let waited = unsafe { libc::waitpid(pid, &mut status, options) };
+        if waited >= 0 {
+            return Ok((waited, status));
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e.into());
+        }
+        on_interrupt(mode)?;
+    }
+}
+
+/// Sleeps for `duration`. In [`Mode::Restart`] an interrupted sleep carries
+/// on with whatever time `nanosleep` says was left.
+pub fn nanosleep(duration: Duration, mode: Mode<'_>) -> Result<(), Error> {
+    let mut request = fd::timespec(duration);
+    loop {
+        let mut remaining = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        if unsafe { libc::nanosleep(&request, &mut remaining) } == 0 {
+            return Ok(());
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e.into());
+        }
+        on_interrupt(mode)?;
+        request = remaining;
+    }
+}
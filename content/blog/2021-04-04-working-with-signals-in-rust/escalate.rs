@@ -0,0 +1,244 @@
+//! Escalating interrupts: ask nicely, then insist.
+//!
+//! The usual contract for `Ctrl+C` in a long-running tool is:
+//!
+//! 1. the first press asks the program to wrap up gracefully;
+//! 2. a second press (while it's still wrapping up) gets a warning that one
+//!    more will kill it;
+//! 3. a third press puts the default disposition back and re-raises the
+//!    signal, so that the process dies of it - with the exit status that
+//!    goes with that - rather than waiting for cleanup to finish.
+//!
+//! The window is measured from the first press of a sequence: a press that
+//! comes after the window has closed starts a new sequence, as the first
+//! press again.
+//! All of the counting happens in the handler, so it works even if the main
+//! thread is stuck somewhere and never gets around to reading the channel.
+
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use libc::c_int;
+
+use crate::exit;
+use crate::fd;
+use crate::pipe::{self, Receiver};
+use crate::sigaction::Error;
+use crate::siginfo::SignalInfo;
+use crate::signal::Signal;
+use crate::sigsafe;
+use crate::source::Source;
+
+/// Only one signal can be escalated at a time; these are its settings and
+/// state, where the handler can get at them.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+/// The current sequence of presses, packed into one word so that the handler
+/// can update it in a single step even if deliveries overlap on different
+/// threads: the time of the first press, in milliseconds, above a 16-bit
+/// count of the presses so far.
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+static WINDOW_MILLIS: AtomicU64 = AtomicU64::new(0);
+static WARNING: AtomicPtr<u8> = AtomicPtr::new(std::ptr::null_mut());
+static WARNING_LEN: AtomicUsize = AtomicUsize::new(0);
+
+const DEFAULT_WINDOW: Duration = Duration::from_secs(5);
+const DEFAULT_WARNING: &str = "Still shutting down; interrupt again to exit immediately\n";
+
+#[derive(Clone, Debug)]
+pub struct Policy {
+    window: Duration,
+    warning: &'static str,
+}
+
+impl Default for Policy {
+    fn default() -> Policy {
+        Policy {
+            window: DEFAULT_WINDOW,
+            warning: DEFAULT_WARNING,
+        }
+    }
+}
+
+impl Policy {
+    pub fn new() -> Policy {
+        Policy::default()
+    }
+
+    /// How close together the presses need to be to escalate. Measured from
+    /// the first press in a sequence.
+    pub fn window(mut self, window: Duration) -> Policy {
+        self.window = window;
+        self
+    }
+
+    /// What to print to stderr on the second press.
+    pub fn warning(mut self, warning: &'static str) -> Policy {
+        self.warning = warning;
+        self
+    }
+}
+
+const COUNT_BITS: u32 = 16;
+const COUNT_MASK: u64 = (1 << COUNT_BITS) - 1;
+
+fn unpack(sequence: u64) -> (u64, u64) {
+    (sequence >> COUNT_BITS, sequence & COUNT_MASK)
+}
+
+fn monotonic_millis() -> u64 {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    now.tv_sec as u64 * 1_000 + now.tv_nsec as u64 / 1_000_000
+}
+
+/// Where a sequence goes after a press at `now`: a new sequence if the last
+/// one has closed, otherwise one more press.
+fn advance(sequence: u64, now: u64, window: u64) -> u64 {
+    let (first, presses) = unpack(sequence);
+    let (first, presses) = if presses == 0 || now.saturating_sub(first) > window {
+        (now, 1)
+    } else {
+        (first, (presses + 1).min(COUNT_MASK))
+    };
+    first << COUNT_BITS | presses
+}
+
+/// Counts a press at `now`, and returns how many presses the sequence has
+/// had.
+fn press(now: u64, window: u64) -> u64 {
+    let mut current = SEQUENCE.load(Ordering::Acquire);
+    loop {
+        let next = advance(current, now, window);
+        match SEQUENCE.compare_exchange_weak(current, next, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => return unpack(next).1,
+            Err(actual) => current = actual,
+        }
+    }
+}
+
+extern "C" fn on_signal(signo: c_int, info: *mut libc::siginfo_t, _context: *mut libc::c_void) {
+    fd::preserving_errno(|| {
+        match press(monotonic_millis(), WINDOW_MILLIS.load(Ordering::Relaxed)) {
+            1 => SHUTDOWN_REQUESTED.store(true, Ordering::Release),
+            2 => {
+                let warning = WARNING.load(Ordering::Acquire);
+                let len = WARNING_LEN.load(Ordering::Acquire);
+                if !warning.is_null() {
+                    let warning = unsafe { std::slice::from_raw_parts(warning, len) };
+                    sigsafe::write_all(libc::STDERR_FILENO, warning);
+                }
+            }
+            _ => unsafe { exit::reset_and_raise(signo) },
+        }
+        if let Some(info) = unsafe { info.as_ref() }.and_then(SignalInfo::from_siginfo) {
+            pipe::forward(&info);
+        }
+    })
+}
+
+/// An installed escalation policy. The first interrupt shows up here as a
+/// signal, so this can be waited on like any other [`Source`].
+#[derive(Debug)]
+pub struct Escalation {
+    receiver: Receiver<Signal>,
+    // Dropped after the receiver, so that our handler is gone before another
+    // escalation can be installed.
+    _active: Active,
+}
+
+#[derive(Debug)]
+struct Active;
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        ACTIVE.store(false, Ordering::Release);
+    }
+}
+
+pub fn install(signal: Signal, policy: Policy) -> Result<Escalation, Error> {
+    if ACTIVE.swap(true, Ordering::AcqRel) {
+        return Err(Error::Registered(signal));
+    }
+    let active = Active;
+    SHUTDOWN_REQUESTED.store(false, Ordering::Release);
+    SEQUENCE.store(0, Ordering::Release);
+    WINDOW_MILLIS.store(
+        policy.window.as_millis().min(u64::MAX as u128) as u64,
+        Ordering::Release,
+    );
+    WARNING_LEN.store(policy.warning.len(), Ordering::Release);
+    WARNING.store(policy.warning.as_ptr() as *mut u8, Ordering::Release);
+
+    let receiver = pipe::channel_with(&[signal], on_signal)?;
+    Ok(Escalation {
+        receiver,
+        _active: active,
+    })
+}
+
+impl Escalation {
+    /// Whether anyone has asked us to stop yet.
+    pub fn shutdown_requested(&self) -> bool {
+        SHUTDOWN_REQUESTED.load(Ordering::Acquire)
+    }
+
+    /// How many times the signal has arrived in the current window.
+    pub fn presses(&self) -> usize {
+        unpack(SEQUENCE.load(Ordering::Acquire)).1 as usize
+    }
+
+    pub fn receiver(&self) -> &Receiver<Signal> {
+        &self.receiver
+    }
+}
+
+impl AsRawFd for Escalation {
+    fn as_raw_fd(&self) -> RawFd {
+        self.receiver.as_raw_fd()
+    }
+}
+
+impl Source for Escalation {
+    fn try_next(&self) -> io::Result<Option<Signal>> {
+        self.receiver.try_recv()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: u64 = 5_000;
+
+    #[test]
+    fn first_press_starts_a_sequence() {
+        assert_eq!(unpack(advance(0, 1_000, WINDOW)), (1_000, 1));
+    }
+
+    #[test]
+    fn presses_inside_the_window_count_up() {
+        let second = advance(advance(0, 1_000, WINDOW), 2_000, WINDOW);
+        assert_eq!(unpack(second), (1_000, 2));
+        let third = advance(second, 1_000 + WINDOW, WINDOW);
+        assert_eq!(unpack(third), (1_000, 3));
+    }
+
+    #[test]
+    fn press_after_the_window_starts_again() {
+        let second = advance(advance(0, 1_000, WINDOW), 2_000, WINDOW);
+        let late = 1_000 + WINDOW + 1;
+        assert_eq!(unpack(advance(second, late, WINDOW)), (late, 1));
+    }
+
+    #[test]
+    fn count_saturates() {
+        let full = 1_000 << COUNT_BITS | COUNT_MASK;
+        assert_eq!(unpack(advance(full, 1_000, WINDOW)), (1_000, COUNT_MASK));
+    }
+}
@@ -0,0 +1,274 @@
+//! A very small single-threaded executor.
+//!
+//! Enough to run the [`future`](crate::future) API without reaching for a
+//! runtime: a queue of tasks that have been woken, and a
+//! [`Reactor`] to sleep in while the queue is empty.
+//! Signals and timers first waited on inside [`Executor::block_on`] are
+//! watched by that same reactor, so their wakers run right here. Wakers can
+//! still be called from other threads, though, so waking a task both queues
+//! it and pokes an `eventfd` that the reactor is waiting on.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::os::unix::io::{AsRawFd, OwnedFd};
+use std::pin::{pin, Pin};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+
+use crate::fd;
+use crate::reactor::{Interest, Reactor};
+
+type Task = Pin<Box<dyn Future<Output = ()>>>;
+
+/// Stands for the future passed to [`Executor::block_on`] in the queue.
+const MAIN: usize = usize::MAX;
+
+/// The tasks that have been woken and are waiting for their turn.
+struct Queue {
+    ready: Mutex<VecDeque<usize>>,
+    wakeup: OwnedFd,
+}
+
+impl Queue {
+    fn push(&self, id: usize) {
+        let mut ready = self.ready.lock().unwrap_or_else(|e| e.into_inner());
+        if !ready.contains(&id) {
+            ready.push_back(id);
+        }
+        drop(ready);
+        fd::post(self.wakeup.as_raw_fd());
+    }
+
+    fn pop(&self) -> Option<usize> {
+        self.ready
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pop_front()
+    }
+
+    /// Clears the eventfd once we've noticed it.
+    fn reset(&self) {
+        let _ = fd::read_counter(self.wakeup.as_raw_fd());
+    }
+}
+
+struct TaskWaker {
+    id: usize,
+    queue: Arc<Queue>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.queue.push(self.id);
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.queue.push(self.id);
+    }
+}
+
+pub struct Executor {
+    queue: Arc<Queue>,
+    reactor: Reactor,
+    /// Indexed by task id; `None` once a task has finished (or while it's
+    /// being polled).
+    tasks: RefCell<Vec<Option<Task>>>,
+}
+
+impl fmt::Debug for Executor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tasks = self.tasks.borrow();
+        f.debug_struct("Executor")
+            .field("tasks", &tasks.iter().filter(|t| t.is_some()).count())
+            .finish()
+    }
+}
+
+impl Executor {
+    pub fn new() -> io::Result<Executor> {
+        let queue = Arc::new(Queue {
+            ready: Mutex::new(VecDeque::new()),
+            wakeup: fd::eventfd(0)?,
+        });
+        let mut reactor = Reactor::new()?;
+        reactor.add(queue.wakeup.as_raw_fd(), Interest::READABLE)?;
+        Ok(Executor {
+            queue,
+            reactor,
+            tasks: RefCell::new(Vec::new()),
+        })
+    }
+
+    /// Adds a task, which runs alongside the others whenever
+    /// [`Executor::block_on`] is running. Tasks don't need to be `Send`:
+    /// they only ever run on this thread.
+    ///
+    /// The returned handle can be awaited for the task's output. Dropping it
+    /// leaves the task running.
+    pub fn spawn<F>(&self, task: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+    {
+        let join = Rc::new(RefCell::new(Join {
+            output: None,
+            waker: None,
+        }));
+        let handle = JoinHandle(Rc::clone(&join));
+        let task = async move {
+            let output = task.await;
+            let waker = {
+                let mut join = join.borrow_mut();
+                join.output = Some(output);
+                join.waker.take()
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        };
+        let mut tasks = self.tasks.borrow_mut();
+        let id = tasks.len();
+        tasks.push(Some(Box::pin(task)));
+        self.queue.push(id);
+        handle
+    }
+
+    fn waker(&self, id: usize) -> Waker {
+        Arc::new(TaskWaker {
+            id,
+            queue: Arc::clone(&self.queue),
+        })
+        .into()
+    }
+
+    /// Runs `future` to completion, along with any spawned tasks in the
+    /// meantime. Tasks that haven't finished by then are left for the next
+    /// call.
+    ///
+    /// Signals and timers first waited on in here stay with this executor's
+    /// reactor, so they'll only wake their task while it's running.
+    pub fn block_on<F: Future>(&mut self, future: F) -> io::Result<F::Output> {
+        let _entered = self.reactor.handle().enter();
+        let mut future = pin!(future);
+        let main_waker = self.waker(MAIN);
+        self.queue.push(MAIN);
+        loop {
+            while let Some(id) = self.queue.pop() {
+                if id == MAIN {
+                    let mut cx = Context::from_waker(&main_waker);
+                    if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                        return Ok(output);
+                    }
+                    continue;
+                }
+                self.run_task(id);
+            }
+            self.reactor.poll(None)?;
+            self.queue.reset();
+        }
+    }
+
+    fn run_task(&self, id: usize) {
+        // Taken out while it runs, so that it can spawn more tasks.
+        let task = self.tasks.borrow_mut().get_mut(id).and_then(Option::take);
+        let mut task = match task {
+            Some(task) => task,
+            None => return,
+        };
+        let waker = self.waker(id);
+        let mut cx = Context::from_waker(&waker);
+        if task.as_mut().poll(&mut cx).is_pending() {
+            self.tasks.borrow_mut()[id] = Some(task);
+        }
+    }
+}
+
+struct Join<T> {
+    output: Option<T>,
+    /// Whoever's waiting for the output.
+    waker: Option<Waker>,
+}
+
+/// The output of a task started with [`Executor::spawn`], once it's done.
+pub struct JoinHandle<T>(Rc<RefCell<Join<T>>>);
+
+impl<T> fmt::Debug for JoinHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JoinHandle")
+            .field("finished", &self.0.borrow().output.is_some())
+            .finish()
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut join = self.0.borrow_mut();
+        match join.output.take() {
+            Some(output) => Poll::Ready(output),
+            None => {
+                join.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use crate::future::{self, AsyncSignals, Either};
+    use crate::pipe;
+    use crate::signal::Signal;
+
+    #[test]
+    fn runs_tasks_alongside_signals_and_timers() {
+        // A signal of our own, so that other tests can't get in the way.
+        let signal = Signal::from_raw(Signal::rt_min().as_raw() + 5).unwrap();
+        let signals = AsyncSignals::new(pipe::channel::<Signal>(&[signal]).unwrap());
+        let mut executor = Executor::new().unwrap();
+
+        let tasks: Vec<_> = (0..3u64)
+            .map(|i| {
+                executor.spawn(async move {
+                    future::sleep(Duration::from_millis(10 * i)).await.unwrap();
+                    i
+                })
+            })
+            .collect();
+        let received = executor.spawn(async move { signals.recv().await.unwrap() });
+        let raised = executor.spawn(async move {
+            future::sleep(Duration::from_millis(20)).await.unwrap();
+            unsafe { libc::raise(signal.as_raw()) };
+        });
+
+        let (outputs, received) = executor
+            .block_on(async {
+                let mut outputs = Vec::new();
+                for task in tasks {
+                    outputs.push(task.await);
+                }
+                raised.await;
+                let timeout = future::sleep(Duration::from_secs(5));
+                (outputs, future::race(received, timeout).await)
+            })
+            .unwrap();
+        assert_eq!(outputs, [0, 1, 2]);
+        assert!(matches!(received, Either::Left(s) if s == signal));
+
+        let raced = executor
+            .block_on(future::race(
+                future::sleep(Duration::from_secs(5)),
+                future::sleep(Duration::from_millis(10)),
+            ))
+            .unwrap();
+        assert!(matches!(raced, Either::Right(Ok(()))));
+    }
+}
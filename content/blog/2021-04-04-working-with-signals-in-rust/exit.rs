@@ -0,0 +1,58 @@
+//! Dying of a signal properly, once we've finished cleaning up.
+//!
+//! If we catch `SIGINT`, tidy up and then return from `main`, our exit status
+//! is 0 and whoever started us (a shell, `make`, a CI runner) will think we
+//! finished normally. The convention is that a process killed by a signal
+//! reports that signal - shells show it as `128 + signo`, so 130 for
+//! `SIGINT` - and programs like `make` and `bash` stop what they're doing
+//! when they see it. The most faithful way to get that is to actually die of
+//! the signal: put the default disposition back and raise it again.
+
+use std::io::{self, Write};
+use std::mem;
+use std::process;
+
+use libc::c_int;
+
+use crate::mask;
+use crate::signal::Signal;
+use crate::sigset::SigSet;
+
+/// The exit status a shell would report for a process killed by `signal`.
+pub fn status_for(signal: Signal) -> i32 {
+    128 + signal.as_raw()
+}
+
+/// Terminates the process as though `signal` had never been caught.
+///
+/// Signals whose default action doesn't terminate the process (or that are
+/// blocked or ignored in a way we can't undo) would let us carry on, so if
+/// we're still around after raising it we exit with `128 + signo` instead.
+/// Either way, this doesn't return.
+pub fn terminate_with(signal: Signal) -> ! {
+    // Make sure anything buffered on stdout makes it out; we're about to skip
+    // the usual teardown.
+    let _ = io::stdout().flush();
+
+    if signal.is_catchable() {
+        unsafe { reset_and_raise(signal.as_raw()) };
+        let _ = mask::pthread_sigmask(libc::SIG_UNBLOCK, Some(SigSet::from(signal)));
+    } else {
+        unsafe { libc::raise(signal.as_raw()) };
+    }
+
+    process::exit(status_for(signal))
+}
+
+/// Puts the default disposition back for `signo` and raises it. If the
+/// signal is blocked - as it is in its own handler - it's delivered, to the
+/// default disposition, as soon as it's unblocked (or the handler returns).
+///
+/// Async-signal-safe, so it can be called from a handler.
+pub(crate) unsafe fn reset_and_raise(signo: c_int) {
+    let mut action: libc::sigaction = mem::zeroed();
+    action.sa_sigaction = libc::SIG_DFL;
+    libc::sigemptyset(&mut action.sa_mask);
+    libc::sigaction(signo, &action, std::ptr::null_mut());
+    libc::raise(signo);
+}
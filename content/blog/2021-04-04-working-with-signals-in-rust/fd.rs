@@ -0,0 +1,154 @@
+//! Small helpers shared by the fd-based delivery mechanisms, and by the
+//! handlers that feed them.
+
+use std::io;
+use std::mem;
+use std::os::unix::io::{FromRawFd, OwnedFd, RawFd};
+use std::time::{Duration, Instant};
+
+use libc::c_int;
+
+/// Converts a timeout to the milliseconds that `poll(2)` wants, rounding up
+/// so that we don't spin on sub-millisecond remainders. `None` waits forever.
+pub(crate) fn poll_timeout(timeout: Option<Duration>) -> c_int {
+    timeout.map_or(-1, |t| {
+        let millis = t.as_micros().saturating_add(999) / 1000;
+        millis.min(c_int::MAX as u128) as c_int
+    })
+}
+
+/// The instant `timeout` from now, or `None` if that's further off than an
+/// `Instant` can represent, in which case it may as well be never.
+pub(crate) fn deadline(timeout: Duration) -> Option<Instant> {
+    Instant::now().checked_add(timeout)
+}
+
+/// Whether `deadline` has passed. `None` never does.
+pub(crate) fn expired(deadline: Option<Instant>) -> bool {
+    deadline.is_some_and(|d| Instant::now() >= d)
+}
+
+/// How long is left until `deadline`, as a timeout for [`wait_readable`].
+pub(crate) fn remaining(deadline: Option<Instant>) -> Option<Duration> {
+    deadline.map(|d| d.saturating_duration_since(Instant::now()))
+}
+
+/// Converts a duration to a `timespec`, for the calls that take one. Anything
+/// too long for a `time_t` is cut down to the longest one there is.
+pub(crate) fn timespec(duration: Duration) -> libc::timespec {
+    libc::timespec {
+        tv_sec: duration.as_secs().min(libc::time_t::MAX as u64) as libc::time_t,
+        tv_nsec: duration.subsec_nanos() as libc::c_long,
+    }
+}
+
+/// Waits for `fd` to become readable. Returns early (without an error) if a
+/// signal interrupts the wait, so callers should be prepared to look again.
+pub(crate) fn wait_readable(fd: RawFd, timeout: Option<Duration>) -> io::Result<()> {
+    wait_readable_any(&[fd], timeout)
+}
+
+/// Like [`wait_readable`], but for whichever of `fds` is readable first.
+pub(crate) fn wait_readable_any(fds: &[RawFd], timeout: Option<Duration>) -> io::Result<()> {
+    let mut pollfds: Vec<libc::pollfd> = fds
+        .iter()
+        .map(|&fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+    let n = pollfds.len() as libc::nfds_t;
+    if unsafe { libc::poll(pollfds.as_mut_ptr(), n, poll_timeout(timeout)) } < 0 {
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Creates a non-blocking, close-on-exec `eventfd`, with any extra `flags`
+/// (such as `EFD_SEMAPHORE`).
+pub(crate) fn eventfd(flags: c_int) -> io::Result<OwnedFd> {
+    let raw = unsafe { libc::eventfd(0, flags | libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+    if raw < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(raw) })
+}
+
+/// Adds one to an eventfd's counter, making it readable. Async-signal-safe.
+/// The only way this can fail is if the counter is about to overflow, in
+/// which case the fd is as readable as it's going to get, so errors are
+/// ignored.
+pub(crate) fn post(fd: RawFd) {
+    let one: u64 = 1;
+    unsafe {
+        libc::write(
+            fd,
+            &one as *const u64 as *const libc::c_void,
+            mem::size_of::<u64>(),
+        )
+    };
+}
+
+/// Reads the counter from an eventfd or a timerfd, resetting it (or, for a
+/// semaphore eventfd, taking one off it). Returns 0 if there was nothing to
+/// read.
+pub(crate) fn read_counter(fd: RawFd) -> io::Result<u64> {
+    let mut count: u64 = 0;
+    let n = unsafe {
+        libc::read(
+            fd,
+            &mut count as *mut u64 as *mut libc::c_void,
+            mem::size_of::<u64>(),
+        )
+    };
+    if n < 0 {
+        let e = io::Error::last_os_error();
+        return match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted => Ok(0),
+            _ => Err(e),
+        };
+    }
+    Ok(count)
+}
+
+/// Creates a non-blocking, close-on-exec `timerfd` on the monotonic clock,
+/// not yet armed.
+pub(crate) fn timerfd() -> io::Result<OwnedFd> {
+    let raw = unsafe {
+        libc::timerfd_create(
+            libc::CLOCK_MONOTONIC,
+            libc::TFD_NONBLOCK | libc::TFD_CLOEXEC,
+        )
+    };
+    if raw < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(raw) })
+}
+
+/// Arms a timerfd to go off `after` from now, and then every `interval` if
+/// there is one. A zero `after` disarms it instead.
+pub(crate) fn set_timer(fd: RawFd, after: Duration, interval: Option<Duration>) -> io::Result<()> {
+    let spec = libc::itimerspec {
+        it_value: timespec(after),
+        it_interval: timespec(interval.unwrap_or_default()),
+    };
+    if unsafe { libc::timerfd_settime(fd, 0, &spec, std::ptr::null_mut()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Runs `f` and puts `errno` back the way it was. Signal handlers need this
+/// around anything that makes system calls, since the code they've
+/// interrupted may be just about to look at `errno`.
+pub(crate) fn preserving_errno<R>(f: impl FnOnce() -> R) -> R {
+    let errno = unsafe { *libc::__errno_location() };
+    let result = f();
+    unsafe { *libc::__errno_location() = errno };
+    result
+}
@@ -0,0 +1,211 @@
+//! Waiting for signals from async code.
+//!
+//! Any [`Source`] can be wrapped up in [`AsyncSignals`], after which
+//! `signals.recv().await` waits for the next signal without blocking the
+//! thread. Nothing here depends on a particular runtime: the fd is watched by
+//! a [`Reactor`](crate::reactor::Reactor) (the [`Executor`](crate::executor::Executor)'s
+//! own, or failing that one on a thread of our own), which wakes the task
+//! through its `Waker` like any other.
+//!
+//! ```no_run
+//! use example::future::AsyncSignals;
+//! use example::pipe;
+//! use example::signal::Signal;
+//!
+//! async fn wait_for_interrupt() -> std::io::Result<Signal> {
+//!     let signals = AsyncSignals::new(pipe::channel::<Signal>(&[Signal::INT]).unwrap());
+//!     signals.recv().await
+//! }
+//! ```
+//!
+//! There's no `Stream` trait in `std` yet; [`AsyncSignals::poll_next`] has the
+//! same shape as `futures::Stream::poll_next`, so adapting it is a one-liner.
+
+use std::fmt;
+use std::future::{self, Future};
+use std::io;
+use std::os::unix::io::{AsRawFd, OwnedFd};
+use std::pin::{pin, Pin};
+use std::sync::OnceLock;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use crate::fd;
+use crate::reactor::Handle;
+use crate::signal::Signal;
+use crate::source::Source;
+
+/// A signal [`Source`] that can be waited on asynchronously.
+pub struct AsyncSignals<S: Source> {
+    source: S,
+    /// The reactor watching the fd, picked the first time we wait.
+    reactor: OnceLock<Handle>,
+}
+
+impl<S: Source> AsyncSignals<S> {
+    pub fn new(source: S) -> AsyncSignals<S> {
+        AsyncSignals {
+            source,
+            reactor: OnceLock::new(),
+        }
+    }
+
+    /// Waits for the next signal.
+    pub fn recv(&self) -> Recv<'_, S> {
+        Recv { signals: self }
+    }
+
+    /// Takes the next signal if there is one, or arranges for the task to be
+    /// woken when there is.
+    pub fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<io::Result<Signal>> {
+        if let Some(signal) = self.source.try_next()? {
+            return Poll::Ready(Ok(signal));
+        }
+        let reactor = match self.reactor.get() {
+            Some(reactor) => reactor,
+            None => {
+                let current = Handle::current()?;
+                self.reactor.get_or_init(|| current)
+            }
+        };
+        reactor.wake_when_readable(self.source.as_raw_fd(), cx.waker())?;
+        // A signal that arrived while we were registering would otherwise go
+        // unnoticed until the next one.
+        match self.source.try_next()? {
+            Some(signal) => Poll::Ready(Ok(signal)),
+            None => Poll::Pending,
+        }
+    }
+
+    /// The signals, as a stream that never ends.
+    pub fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<io::Result<Signal>>> {
+        self.poll_recv(cx).map(Some)
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.source
+    }
+}
+
+impl<S: Source> Drop for AsyncSignals<S> {
+    fn drop(&mut self) {
+        if let Some(reactor) = self.reactor.get() {
+            reactor.forget(self.source.as_raw_fd());
+        }
+    }
+}
+
+impl<S: Source> fmt::Debug for AsyncSignals<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AsyncSignals")
+            .field("fd", &self.source.as_raw_fd())
+            .finish()
+    }
+}
+
+/// The future returned by [`AsyncSignals::recv`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct Recv<'a, S: Source> {
+    signals: &'a AsyncSignals<S>,
+}
+
+impl<S: Source> Future for Recv<'_, S> {
+    type Output = io::Result<Signal>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<Signal>> {
+        self.signals.poll_recv(cx)
+    }
+}
+
+/// A future that completes once a duration has passed, using a `timerfd`
+/// watched the same way as the signals.
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct Delay {
+    /// `None` if it's too far off to represent, in which case it never
+    /// comes.
+    deadline: Option<Instant>,
+    timer: Option<(OwnedFd, Handle)>,
+}
+
+/// Waits for `duration`.
+pub fn sleep(duration: Duration) -> Delay {
+    Delay {
+        deadline: fd::deadline(duration),
+        timer: None,
+    }
+}
+
+impl Delay {
+    /// Arms the timer for whatever's left until the deadline, creating it
+    /// the first time round.
+    fn arm(&mut self, remaining: Duration) -> io::Result<&(OwnedFd, Handle)> {
+        if self.timer.is_none() {
+            self.timer = Some((fd::timerfd()?, Handle::current()?));
+        }
+        let timer = self.timer.as_ref().expect("created above");
+
+        // Clear any earlier expiry, so that the fd is only readable once the
+        // new time is up.
+        fd::read_counter(timer.0.as_raw_fd())?;
+        fd::set_timer(timer.0.as_raw_fd(), remaining, None)?;
+        Ok(timer)
+    }
+}
+
+impl Future for Delay {
+    type Output = io::Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // Never arm the timer for a deadline we couldn't represent: it would
+        // go off early, or not at all.
+        let deadline = match self.deadline {
+            Some(deadline) => deadline,
+            None => return Poll::Pending,
+        };
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining == Duration::from_secs(0) {
+            return Poll::Ready(Ok(()));
+        }
+        let (timer, reactor) = self.arm(remaining)?;
+        reactor.wake_when_readable(timer.as_raw_fd(), cx.waker())?;
+        Poll::Pending
+    }
+}
+
+impl Drop for Delay {
+    fn drop(&mut self) {
+        if let Some((timer, reactor)) = &self.timer {
+            reactor.forget(timer.as_raw_fd());
+        }
+    }
+}
+
+/// Which of two futures finished first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Either<A, B> {
+    Left(A),
+    Right(B),
+}
+
+/// Runs two futures concurrently, and returns the output of whichever
+/// finishes first. The other is dropped, which for most futures cancels it.
+/// If both are ready at once, `a` wins.
+pub async fn race<A: Future, B: Future>(a: A, b: B) -> Either<A::Output, B::Output> {
+    let mut a = pin!(a);
+    let mut b = pin!(b);
+    future::poll_fn(|cx| {
+        if let Poll::Ready(output) = a.as_mut().poll(cx) {
+            return Poll::Ready(Either::Left(output));
+        }
+        if let Poll::Ready(output) = b.as_mut().poll(cx) {
+            return Poll::Ready(Either::Right(output));
+        }
+        Poll::Pending
+    })
+    .await
+}
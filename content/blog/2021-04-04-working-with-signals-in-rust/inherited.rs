@@ -0,0 +1,115 @@
+//! Leaving alone the signals we were told to ignore.
+//!
+//! Ignored signals stay ignored across `exec`. That's how `nohup` keeps a
+//! command running after the terminal goes away, and how a non-interactive
+//! shell keeps `Ctrl+C` from reaching the jobs it starts in the background:
+//! both set `SIG_IGN` and let the program inherit it. A program that blindly
+//! installs its own handler undoes that choice. Well-behaved tools look
+//! first, and only handle the signal if it wasn't already being ignored.
+
+use crate::sigaction::{self, Action, Disposition, Error, Handler};
+use crate::signal::Signal;
+
+/// What to do about a signal that's currently being ignored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IfIgnored {
+    /// Install our handler anyway.
+    Override,
+    /// Don't install anything, and leave the signal ignored.
+    Skip,
+    /// Install our handler anyway, but say so on stderr.
+    Warn,
+    /// Refuse, with [`Error::Ignored`].
+    Fail,
+}
+
+/// Decides, signal by signal, what to do about inherited `SIG_IGN`s.
+#[derive(Clone, Debug)]
+pub struct Policy {
+    default: IfIgnored,
+    signals: Vec<(Signal, IfIgnored)>,
+}
+
+impl Default for Policy {
+    fn default() -> Policy {
+        Policy {
+            default: IfIgnored::Skip,
+            signals: Vec::new(),
+        }
+    }
+}
+
+impl Policy {
+    /// A policy that skips every signal that's being ignored.
+    pub fn new() -> Policy {
+        Policy::default()
+    }
+
+    /// What to do for signals that haven't been given a choice of their own.
+    pub fn otherwise(mut self, choice: IfIgnored) -> Policy {
+        self.default = choice;
+        self
+    }
+
+    pub fn signal(mut self, signal: Signal, choice: IfIgnored) -> Policy {
+        self.signals.retain(|(s, _)| *s != signal);
+        self.signals.push((signal, choice));
+        self
+    }
+
+    pub fn choice(&self, signal: Signal) -> IfIgnored {
+        self.signals
+            .iter()
+            .find(|(s, _)| *s == signal)
+            .map_or(self.default, |(_, choice)| *choice)
+    }
+}
+
+/// Whether `signal` is currently ignored. Called before anything else in the
+/// process has touched it, that's the same as asking whether it was ignored
+/// when we were started.
+pub fn is_ignored(signal: Signal) -> Result<bool, Error> {
+    Ok(matches!(
+        sigaction::current(signal)?.handler(),
+        Handler::Ignore
+    ))
+}
+
+/// Whether it's all right to install a handler for `signal`, according to
+/// `policy`. Warns on stderr if the policy says to.
+pub fn check(signal: Signal, policy: &Policy) -> Result<bool, Error> {
+    if !is_ignored(signal)? {
+        return Ok(true);
+    }
+    match policy.choice(signal) {
+        IfIgnored::Override => Ok(true),
+        IfIgnored::Skip => Ok(false),
+        IfIgnored::Warn => {
+            eprintln!(
+                "warning: {} was ignored when we started; handling it anyway",
+                signal
+            );
+            Ok(true)
+        }
+        IfIgnored::Fail => Err(Error::Ignored(signal)),
+    }
+}
+
+/// Installs `action` for `signal`, unless it's being ignored and `policy`
+/// says to leave it that way. Returns the previous disposition if anything
+/// was installed.
+///
+/// # Safety
+///
+/// As for [`sigaction::install`].
+pub unsafe fn install(
+    signal: Signal,
+    action: &Action,
+    policy: &Policy,
+) -> Result<Option<Disposition>, Error> {
+    if check(signal, policy)? {
+        sigaction::install(signal, action).map(Some)
+    } else {
+        Ok(None)
+    }
+}
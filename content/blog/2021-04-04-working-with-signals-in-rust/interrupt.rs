@@ -0,0 +1,191 @@
+//! Interrupting a thread that's stuck in a blocking call.
+//!
+//! A `read` or `accept` that's waiting for a peer that never shows up can't
+//! be cancelled from another thread by any ordinary means. It can be
+//! interrupted by a signal, though: if the handler was installed without
+//! `SA_RESTART`, the call fails with `EINTR` once the handler returns. So we
+//! set aside a real-time signal for the purpose, and send it to exactly the
+//! thread we want to stop with `pthread_kill`.
+//!
+//! Like Java's `Thread.interrupt()`, this also sets a flag that belongs to the
+//! target thread, so that it can tell an interruption from some other signal
+//! and so that an interruption that arrives between blocking calls isn't
+//! lost. Check [`interrupted`] before blocking, and again on `EINTR`:
+//!
+//! ```no_run
+//! use std::io::{self, Read};
+//! use std::net::TcpStream;
+//!
+//! use example::interrupt;
+//!
+//! fn read_until_interrupted(stream: &mut TcpStream, buf: &mut [u8]) -> io::Result<usize> {
+//!     loop {
+//!         if interrupt::interrupted() {
+//!             return Err(io::ErrorKind::Interrupted.into());
+//!         }
+//!         match stream.read(buf) {
+//!             Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+//!             result => return result,
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! There's still a gap between checking the flag and blocking, in which an
+//! interrupt will be missed; calling [`InterruptHandle::interrupt`] again
+//! closes it. Beware, too, that some of the standard library quietly retries
+//! on `EINTR` (`TcpListener::accept` does, for one), and so can't be
+//! interrupted this way.
+
+use std::cell::RefCell;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use libc::c_int;
+
+use crate::realtime::{self, Allocation};
+use crate::sigaction::{self, Action, Error, Handler};
+use crate::signal::Signal;
+
+/// The signal we send, once it's been picked and its handler installed. It's
+/// never given back.
+static SIGNAL: Mutex<Option<Allocation>> = Mutex::new(None);
+
+thread_local! {
+    static CURRENT: RefCell<Option<Registration>> = const { RefCell::new(None) };
+}
+
+extern "C" fn on_interrupt(_signo: c_int) {
+    // Nothing to do: the flag was set before the signal was sent, and just
+    // having run a handler installed without SA_RESTART is enough to make the
+    // interrupted syscall fail with EINTR.
+}
+
+fn signal() -> Result<Signal, Error> {
+    let mut allocated = SIGNAL.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(allocation) = &*allocated {
+        return Ok(allocation.signal());
+    }
+    let allocation =
+        realtime::allocate().ok_or_else(|| io::Error::other("no real-time signals left"))?;
+    let signal = allocation.signal();
+    unsafe { sigaction::install(signal, &Action::new(Handler::Function(on_interrupt))) }?;
+    *allocated = Some(allocation);
+    Ok(signal)
+}
+
+struct Shared {
+    thread: libc::pthread_t,
+    interrupted: AtomicBool,
+    /// Cleared as the thread exits, after which its `pthread_t` might belong
+    /// to some other thread. Held while signalling so that it can't change
+    /// under us.
+    alive: Mutex<bool>,
+}
+
+/// Lives in the thread's thread-locals, and marks it as gone when they're
+/// destroyed.
+struct Registration(Arc<Shared>);
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        *self.0.alive.lock().unwrap_or_else(|e| e.into_inner()) = false;
+    }
+}
+
+/// Lets other threads interrupt the thread it was made for.
+#[derive(Clone)]
+pub struct InterruptHandle {
+    shared: Arc<Shared>,
+    signal: Signal,
+}
+
+impl InterruptHandle {
+    /// A handle for the calling thread.
+    pub fn current() -> Result<InterruptHandle, Error> {
+        let signal = signal()?;
+        let shared = CURRENT.with(|current| {
+            let mut current = current.borrow_mut();
+            let registration = current.get_or_insert_with(|| {
+                Registration(Arc::new(Shared {
+                    thread: unsafe { libc::pthread_self() },
+                    interrupted: AtomicBool::new(false),
+                    alive: Mutex::new(true),
+                }))
+            });
+            Arc::clone(&registration.0)
+        });
+        Ok(InterruptHandle { shared, signal })
+    }
+
+    /// Sets the thread's interrupted flag, and breaks it out of whatever
+    /// blocking call it's in. Does nothing once the thread has exited.
+    pub fn interrupt(&self) -> Result<(), Error> {
+        self.shared.interrupted.store(true, Ordering::Release);
+        let alive = self.shared.alive.lock().unwrap_or_else(|e| e.into_inner());
+        if !*alive {
+            return Ok(());
+        }
+        match unsafe { libc::pthread_kill(self.shared.thread, self.signal.as_raw()) } {
+            0 => Ok(()),
+            e => Err(io::Error::from_raw_os_error(e).into()),
+        }
+    }
+
+    /// Whether the thread has been interrupted and hasn't yet noticed.
+    pub fn is_interrupted(&self) -> bool {
+        self.shared.interrupted.load(Ordering::Acquire)
+    }
+}
+
+impl std::fmt::Debug for InterruptHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InterruptHandle")
+            .field("signal", &self.signal)
+            .field("interrupted", &self.is_interrupted())
+            .finish()
+    }
+}
+
+/// Starts a thread that can be interrupted through the returned handle.
+pub fn spawn<F, T>(f: F) -> Result<(JoinHandle<T>, InterruptHandle), Error>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    // Pick the signal here, so that the thread doesn't have anything to
+    // report but its handle.
+    signal()?;
+    let (tx, rx) = mpsc::channel();
+    let thread = thread::Builder::new().spawn(move || {
+        let _ = tx.send(InterruptHandle::current());
+        drop(tx);
+        f()
+    })?;
+    match rx.recv() {
+        Ok(handle) => Ok((thread, handle?)),
+        // The thread died before it could send anything back; whatever
+        // killed it will come out of join.
+        Err(_) => Err(io::Error::other("the thread exited before it started").into()),
+    }
+}
+
+/// Whether the calling thread has been interrupted, clearing the flag as it
+/// goes. Threads that nobody has asked for a handle to are never
+/// interrupted.
+pub fn interrupted() -> bool {
+    CURRENT.with(|current| match &*current.borrow() {
+        Some(registration) => registration.0.interrupted.swap(false, Ordering::AcqRel),
+        None => false,
+    })
+}
+
+/// Like [`interrupted`], but leaves the flag alone.
+pub fn is_interrupted() -> bool {
+    CURRENT.with(|current| match &*current.borrow() {
+        Some(registration) => registration.0.interrupted.load(Ordering::Acquire),
+        None => false,
+    })
+}
@@ -0,0 +1,35 @@
+//! The bits and pieces from "Working with signals in rust", pulled out of
+//! `main` so that they can be reused.
+//!
+//! Everything in here is Linux-only and sits directly on top of `libc`.
+
+pub mod block;
+pub mod cancel;
+pub mod chain;
+pub mod channel;
+pub mod children;
+pub mod coalesce;
+pub mod eintr;
+pub mod escalate;
+pub mod executor;
+pub mod exit;
+mod fd;
+pub mod future;
+pub mod inherited;
+pub mod interrupt;
+mod mask;
+pub mod pipe;
+pub mod reactor;
+pub mod realtime;
+pub mod scoped;
+pub mod select;
+pub mod shutdown;
+pub mod sigaction;
+pub mod siginfo;
+pub mod signal;
+pub mod signalfd;
+pub mod sigsafe;
+pub mod sigset;
+pub mod sleep;
+pub mod source;
+pub mod waiter;
@@ -1,15 +1,242 @@
+use std::env;
+use std::io;
+use std::process::{self, Command};
+use std::sync::mpsc::TryRecvError;
+use std::thread;
 use std::time::Duration;
 
-extern "C" fn handle_interrupt(_sig: libc::c_int) {
+use example::cancel::CancellationToken;
+use example::channel;
+use example::children::Reaper;
+use example::coalesce::{self, Measurement};
+use example::escalate::{self, Escalation, Policy};
+use example::executor::Executor;
+use example::exit;
+use example::future::{self, AsyncSignals, Either};
+use example::inherited;
+use example::realtime;
+use example::select::Select;
+use example::shutdown::{Hook, Registry};
+use example::signal::Signal;
+use example::source::Source;
+
+const INTERRUPT: Signal = Signal::INT.catchable();
+
+/// How long to give the job before giving up on it. A little longer than it
+/// ought to take.
+const JOB_TIMEOUT: Duration = Duration::from_secs(15);
+
+const USAGE: &str = "usage: example [async | children | coalesce [SENDS]]";
+
+fn handle_interrupt() {
     println!("Sorry we didn't get the chance to finish");
 }
 
-fn main() {
+/// Stands in for some real work, checking in with `token` between steps.
+/// Returns whether it got to the end.
+fn long_running_job(token: &CancellationToken) -> bool {
+    for _ in 0..10 {
+        if token.wait_timeout(Duration::from_secs(1)) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Starts listening for SIGINT, unless it's not ours to handle: run in the
+/// background by a shell, or under `nohup`, it may have been ignored on our
+/// behalf.
+fn interrupts() -> Option<Escalation> {
+    let watch = inherited::check(INTERRUPT, &inherited::Policy::new())
+        .expect("failed to look up the SIGINT disposition");
+    if watch {
+        Some(escalate::install(INTERRUPT, Policy::new()).expect("failed to install SIGINT handler"))
+    } else {
+        None
+    }
+}
+
+fn shutdown_hooks() -> Registry {
+    let mut hooks = Registry::new();
+    hooks
+        .register(Hook::new("goodbye", || println!("Goodbye")))
+        .expect("hook names are unique");
+    hooks
+}
+
+/// Runs the shutdown hooks, and then dies of `cancelled_by` if that's what
+/// stopped us.
+fn finish(hooks: Registry, cancelled_by: Option<Signal>) {
+    let report = hooks.run().expect("shutdown hooks can't be ordered");
+    if !report.is_clean() {
+        eprint!("{}", report);
+    }
+
+    if let Some(signal) = cancelled_by {
+        exit::terminate_with(signal);
+    }
+}
+
+fn hello() {
     println!("Hello");
-    unsafe { 
-        libc::signal(libc::SIGINT, handle_interrupt as libc::sighandler_t); 
+    let interrupts = interrupts();
+    let hooks = shutdown_hooks();
+
+    let token = CancellationToken::new();
+    let (done_tx, done_rx) = channel::channel().expect("failed to create a channel");
+    let job = {
+        let token = token.clone();
+        thread::spawn(move || {
+            let _ = done_tx.send(long_running_job(&token));
+        })
+    };
+
+    let mut select = Select::new();
+    let done = select.recv(&done_rx);
+    let interrupted = interrupts.as_ref().map(|i| select.recv(i));
+    let timeout = select.timeout(JOB_TIMEOUT);
+    loop {
+        let ready = select.wait().expect("failed to wait for the job");
+        if ready == done {
+            match done_rx.try_recv() {
+                Ok(true) => break,
+                // The job only stops early if it's been cancelled.
+                Ok(false) | Err(TryRecvError::Disconnected) => {
+                    handle_interrupt();
+                    break;
+                }
+                Err(TryRecvError::Empty) => {}
+            }
+        } else if Some(ready) == interrupted {
+            let interrupts = interrupts.as_ref().expect("only selected if installed");
+            if let Some(signal) = interrupts.try_next().expect("failed to read SIGINT") {
+                token.cancel_with(signal);
+                handle_interrupt();
+                break;
+            }
+        } else if ready == timeout {
+            token.cancel();
+            println!("Gave up waiting for the job after {:?}", JOB_TIMEOUT);
+            break;
+        }
     }
+    let _ = job.join();
+
+    finish(hooks, token.cancelled_by());
+}
 
-    std::thread::sleep(Duration::from_secs(10)); 
-    println!("Goodbye");
-}
\ No newline at end of file
+/// The job from `hello`, as a future. Dropping it is all it takes to stop it.
+async fn main_job() -> io::Result<()> {
+    for _ in 0..10 {
+        future::sleep(Duration::from_secs(1)).await?;
+    }
+    Ok(())
+}
+
+/// `hello` again, with the job, SIGINT and the timeout as futures racing each
+/// other on a single thread.
+fn hello_async() {
+    println!("Hello");
+    let interrupts = interrupts().map(AsyncSignals::new);
+    let hooks = shutdown_hooks();
+
+    let interrupted = async {
+        match &interrupts {
+            Some(interrupts) => interrupts.recv().await,
+            None => std::future::pending().await,
+        }
+    };
+    let timeout = future::sleep(JOB_TIMEOUT);
+    let mut executor = Executor::new().expect("failed to start the executor");
+    let job = executor.spawn(main_job());
+    let outcome = executor
+        .block_on(future::race(job, future::race(interrupted, timeout)))
+        .expect("failed to wait for the job");
+
+    let mut cancelled_by = None;
+    match outcome {
+        Either::Left(finished) => finished.expect("the job failed"),
+        Either::Right(Either::Left(interrupted)) => {
+            cancelled_by = Some(interrupted.expect("failed to read SIGINT"));
+            handle_interrupt();
+        }
+        Either::Right(Either::Right(timed_out)) => {
+            timed_out.expect("the timeout failed");
+            println!("Gave up waiting for the job after {:?}", JOB_TIMEOUT);
+        }
+    }
+
+    finish(hooks, cancelled_by);
+}
+
+/// Starts a few children that come to different ends, all at about the same
+/// time, and reports on each of them as it's reaped.
+fn reap_children() {
+    let reaper = Reaper::new().expect("failed to listen for SIGCHLD");
+    let scripts = [
+        "exit 0",
+        "exit 3",
+        "kill -TERM $$",
+        "ulimit -c 0; kill -SEGV $$",
+    ];
+    for script in &scripts {
+        // The reaper does the waiting, so there's no need to keep the `Child`.
+        let pid = Command::new("sh")
+            .args(["-c", script])
+            .spawn()
+            .expect("failed to start a child")
+            .id();
+        println!("started child {}: {}", pid, script);
+    }
+    while let Some(exited) = reaper.recv().expect("failed to reap children") {
+        println!("{}", exited);
+    }
+}
+
+/// Sends a standard and a real-time signal `sends` times each while they're
+/// blocked, and compares how many of each make it through.
+fn compare_coalescing(sends: usize) {
+    let realtime = realtime::allocate().expect("no real-time signals left");
+    let rows: Vec<Measurement> = [Signal::USR1, realtime.signal()]
+        .iter()
+        .map(|&signal| coalesce::measure(signal, sends).expect("failed to measure coalescing"))
+        .collect();
+
+    println!(
+        "{:<12} {:>8} {:>8} {:>8}",
+        "signal", "sent", "handled", "lost"
+    );
+    for row in rows {
+        println!(
+            "{:<12} {:>8} {:>8} {:>8}",
+            row.signal.to_string(),
+            row.sent,
+            row.handled,
+            row.lost()
+        );
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    match args.next().as_deref() {
+        None => hello(),
+        Some("async") => hello_async(),
+        Some("children") => reap_children(),
+        Some("coalesce") => {
+            let sends = match args.next().map(|n| n.parse()) {
+                None => 1000,
+                Some(Ok(n)) => n,
+                Some(Err(_)) => {
+                    eprintln!("{}", USAGE);
+                    process::exit(2);
+                }
+            };
+            compare_coalescing(sends);
+        }
+        Some(_) => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    }
+}
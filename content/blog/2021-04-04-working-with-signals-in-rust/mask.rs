@@ -0,0 +1,309 @@
+//! Changing the signal mask of every thread in the process, not just the
+//! calling one.
+//!
+//! `pthread_sigmask` only affects the calling thread, and new threads inherit
+//! the mask of whoever spawned them. That's fine if you get in before anything
+//! else has started a thread, but libraries are free to spawn threads behind
+//! our backs, and any one of them with a signal unblocked will happily take
+//! delivery of it.
+//!
+//! To reach threads we didn't start, we send each of them a private signal,
+//! `SIGRTMAX`. The kernel restores the interrupted thread's mask from the
+//! `ucontext` when the handler returns, so by editing `uc_sigmask` in there
+//! the handler can change the mask of the thread it's running on.
+//!
+//! That only works for threads that can receive `SIGRTMAX`. A thread that
+//! blocks it (with `SigSet::all()`, say) is skipped if it already has every
+//! signal we're blocking blocked too, and is otherwise an error rather than
+//! something to wait for, so threads shouldn't block `SIGRTMAX` while anything
+//! here is in use. For the same
+//! reason, `SIGRTMAX` itself can't be blocked this way.
+//!
+//! Several owners (a [`SignalFd`](crate::signalfd::SignalFd), a
+//! [`Waiter`](crate::waiter::Waiter)) can need the same signal blocked at
+//! once, so blocks are counted per signal, and a signal is only unblocked
+//! again once the last of them lets go of it.
+
+use std::fs;
+use std::io;
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libc::{c_int, pid_t};
+
+use crate::sigaction::{self, Error, Handler};
+use crate::signal::Signal;
+use crate::sigset::SigSet;
+
+/// The mask we had before we first changed it, which is what children
+/// should get back.
+static INHERITED: AtomicU64 = AtomicU64::new(0);
+static INHERITED_SAVED: AtomicBool = AtomicBool::new(false);
+
+/// Signals to add to, and remove from, the mask of the thread we're visiting.
+static SWEEP_BLOCK: AtomicU64 = AtomicU64::new(0);
+static SWEEP_UNBLOCK: AtomicU64 = AtomicU64::new(0);
+/// The thread being visited, or 0 between visits. The sweep signal can turn
+/// up late in a thread that had it blocked, long after we've given up on
+/// that thread; the handler leaves any thread but this one alone.
+static SWEEP_TARGET: AtomicI32 = AtomicI32::new(0);
+/// The last thread to have run the handler for [`SWEEP_TARGET`].
+static SWEEP_ACKED: AtomicI32 = AtomicI32::new(0);
+
+struct State {
+    /// Whether the sweep handler is installed. Once it is, it stays, so
+    /// that a late sweep signal never meets the default action (which would
+    /// kill the process).
+    installed: bool,
+    /// How many owners need each signal blocked, indexed by signal number.
+    counts: [usize; 65],
+    /// The signals we blocked ourselves, as opposed to ones that were
+    /// already blocked when the first owner asked for them. Only these are
+    /// unblocked when their count drops to zero.
+    owned: SigSet,
+}
+
+/// Held for the whole of a sweep.
+static STATE: Mutex<State> = Mutex::new(State {
+    installed: false,
+    counts: [0; 65],
+    owned: SigSet::empty(),
+});
+
+/// How long to wait for each thread to run the sweep handler.
+const SWEEP_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// `pthread_sigmask(2)`, returning the previous mask. With `set` as `None`
+/// it only reads the mask.
+pub(crate) fn pthread_sigmask(how: c_int, set: Option<SigSet>) -> io::Result<SigSet> {
+    let set = set.map(SigSet::to_sigset);
+    let set = set.as_ref().map_or(std::ptr::null(), |s| s as *const _);
+    let mut old: libc::sigset_t = unsafe { mem::zeroed() };
+    match unsafe { libc::pthread_sigmask(how, set, &mut old) } {
+        0 => Ok(SigSet::from_sigset(&old)),
+        e => Err(io::Error::from_raw_os_error(e)),
+    }
+}
+
+extern "C" fn on_sweep(_signo: c_int, _info: *mut libc::siginfo_t, context: *mut libc::c_void) {
+    let tid = unsafe { libc::gettid() };
+    if SWEEP_TARGET.load(Ordering::Acquire) != tid {
+        return;
+    }
+    let context = context as *mut libc::ucontext_t;
+    let block = SigSet::from_bits(SWEEP_BLOCK.load(Ordering::Acquire));
+    let unblock = SigSet::from_bits(SWEEP_UNBLOCK.load(Ordering::Acquire));
+    unsafe {
+        for signal in block.iter() {
+            libc::sigaddset(&mut (*context).uc_sigmask, signal.as_raw());
+        }
+        for signal in unblock.iter() {
+            libc::sigdelset(&mut (*context).uc_sigmask, signal.as_raw());
+        }
+    }
+    SWEEP_ACKED.store(tid, Ordering::Release);
+}
+
+/// Blocks `signals` in every thread, on behalf of one owner, who should hand
+/// them back to [`release`] when they're done.
+///
+/// The calling thread is done first, so threads it spawns from here on will
+/// inherit the new mask. Threads spawned concurrently by other threads may
+/// be missed. If any thread can't be reached, the threads that were already
+/// changed are put back before the error is returned.
+pub(crate) fn block(signals: SigSet) -> Result<(), Error> {
+    let sweep = Signal::rt_max();
+    if signals.contains(sweep) {
+        return Err(Error::Registered(sweep));
+    }
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    if !state.installed {
+        install(sweep)?;
+        state.installed = true;
+    }
+
+    let before = pthread_sigmask(libc::SIG_BLOCK, None).map_err(Error::Mask)?;
+    if !INHERITED_SAVED.load(Ordering::Acquire) {
+        INHERITED.store(before.bits(), Ordering::Release);
+        INHERITED_SAVED.store(true, Ordering::Release);
+    }
+    let first: SigSet = signals
+        .iter()
+        .filter(|signal| state.counts[signal.as_raw() as usize] == 0)
+        .collect();
+    let added = first - before;
+    set_all_threads(added, SigSet::empty())?;
+
+    for signal in signals.iter() {
+        state.counts[signal.as_raw() as usize] += 1;
+    }
+    state.owned |= added;
+    Ok(())
+}
+
+/// Gives back signals taken with [`block`]. Each is unblocked in every
+/// thread once nobody else needs it, unless it was already blocked before we
+/// got to it.
+pub(crate) fn release(signals: SigSet) -> Result<(), Error> {
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let mut last = SigSet::empty();
+    for signal in signals.iter() {
+        let count = &mut state.counts[signal.as_raw() as usize];
+        if *count > 0 {
+            *count -= 1;
+            if *count == 0 {
+                last.insert(signal);
+            }
+        }
+    }
+    let unblock = last & state.owned;
+    state.owned -= unblock;
+    set_all_threads(SigSet::empty(), unblock)
+}
+
+/// Installs the sweep handler, as long as nobody else has a handler there.
+fn install(sweep: Signal) -> Result<(), Error> {
+    match sigaction::current(sweep)?.handler() {
+        Handler::Default => {}
+        _ => return Err(Error::Registered(sweep)),
+    }
+    let mut action: libc::sigaction = unsafe { mem::zeroed() };
+    action.sa_sigaction = on_sweep as *const () as libc::sighandler_t;
+    action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+    unsafe { libc::sigemptyset(&mut action.sa_mask) };
+    sigaction::sigaction(sweep, Some(&action))?;
+    Ok(())
+}
+
+/// Blocks `block` and unblocks `unblock` in every thread of the process,
+/// putting everything back if that fails partway.
+fn set_all_threads(block: SigSet, unblock: SigSet) -> Result<(), Error> {
+    if block.is_empty() && unblock.is_empty() {
+        return Ok(());
+    }
+    set_this_thread(block, unblock).map_err(Error::Mask)?;
+    let mut swept = Vec::new();
+    let result = signal_other_threads(block, unblock, &mut swept);
+    if result.is_err() {
+        for &tid in &swept {
+            let _ = signal_thread(tid, unblock, block);
+        }
+        let _ = set_this_thread(unblock, block);
+    }
+    result.map_err(Error::Mask)
+}
+
+/// The mask this process started with, as far as we can tell: the calling
+/// thread's mask from just before the first call to [`block`], or the
+/// current one if nothing has been blocked yet.
+pub(crate) fn inherited() -> io::Result<SigSet> {
+    if INHERITED_SAVED.load(Ordering::Acquire) {
+        Ok(SigSet::from_bits(INHERITED.load(Ordering::Acquire)))
+    } else {
+        pthread_sigmask(libc::SIG_BLOCK, None)
+    }
+}
+
+fn set_this_thread(block: SigSet, unblock: SigSet) -> io::Result<()> {
+    pthread_sigmask(libc::SIG_BLOCK, Some(block))?;
+    if let Err(e) = pthread_sigmask(libc::SIG_UNBLOCK, Some(unblock)) {
+        let _ = pthread_sigmask(libc::SIG_UNBLOCK, Some(block));
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Visits every thread but this one, one at a time, adding each one that
+/// picked up the new mask to `swept`.
+fn signal_other_threads(block: SigSet, unblock: SigSet, swept: &mut Vec<pid_t>) -> io::Result<()> {
+    let me = unsafe { libc::gettid() };
+    for entry in fs::read_dir("/proc/self/task")? {
+        let tid: pid_t = match entry?.file_name().to_str().and_then(|n| n.parse().ok()) {
+            Some(tid) => tid,
+            None => continue,
+        };
+        if tid == me {
+            continue;
+        }
+        if signal_thread(tid, block, unblock)? {
+            swept.push(tid);
+        }
+    }
+    Ok(())
+}
+
+/// The signals `tid` has blocked, or `None` if it has exited.
+fn thread_mask(tid: pid_t) -> io::Result<Option<SigSet>> {
+    let status = match fs::read_to_string(format!("/proc/self/task/{}/status", tid)) {
+        Ok(status) => status,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("SigBlk:"))
+        .and_then(|hex| u64::from_str_radix(hex.trim(), 16).ok())
+        .map(|bits| Some(SigSet::from_bits(bits)))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no SigBlk in thread status"))
+}
+
+/// Has `tid` run the sweep handler, if its mask needs changing. Returns
+/// false if it didn't.
+fn signal_thread(tid: pid_t, block: SigSet, unblock: SigSet) -> io::Result<bool> {
+    let sweep = Signal::rt_max();
+    let mask = match thread_mask(tid)? {
+        Some(mask) => mask,
+        None => return Ok(false),
+    };
+    if (block - mask).is_empty() && (unblock & mask).is_empty() {
+        return Ok(false);
+    }
+    if mask.contains(sweep) {
+        // We can't have blocked anything in this thread either, so there's
+        // nothing of ours to unblock.
+        if (block - mask).is_empty() {
+            return Ok(false);
+        }
+        return Err(io::Error::other(format!(
+            "thread {} has {} blocked, so its signal mask can't be changed",
+            tid, sweep
+        )));
+    }
+
+    SWEEP_BLOCK.store(block.bits(), Ordering::Release);
+    SWEEP_UNBLOCK.store(unblock.bits(), Ordering::Release);
+    SWEEP_ACKED.store(0, Ordering::Release);
+    SWEEP_TARGET.store(tid, Ordering::Release);
+    let result = wait_for_ack(sweep, tid);
+    SWEEP_TARGET.store(0, Ordering::Release);
+    result
+}
+
+fn wait_for_ack(sweep: Signal, tid: pid_t) -> io::Result<bool> {
+    let pid = unsafe { libc::getpid() };
+    if unsafe { libc::syscall(libc::SYS_tgkill, pid, tid, sweep.as_raw()) } != 0 {
+        let e = io::Error::last_os_error();
+        // ESRCH just means the thread has exited since we listed it.
+        return match e.raw_os_error() {
+            Some(libc::ESRCH) => Ok(false),
+            _ => Err(e),
+        };
+    }
+    let deadline = Instant::now() + SWEEP_TIMEOUT;
+    while SWEEP_ACKED.load(Ordering::Acquire) != tid {
+        if Instant::now() >= deadline {
+            if fs::metadata(format!("/proc/self/task/{}", tid)).is_err() {
+                return Ok(false);
+            }
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("thread {} didn't pick up the new signal mask", tid),
+            ));
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    Ok(true)
+}
@@ -0,0 +1,251 @@
+//! The self-pipe trick: getting signals out of the handler and back into
+//! application code.
+//!
+//! The handler can't take locks or allocate, but `write(2)` is on the list of
+//! async-signal-safe functions. So we give the handler the write end of a
+//! pipe, have it write what it knows about the signal there (a
+//! [`SignalInfo`]), and let the application read
+//! the other end whenever it likes: blocking, with a timeout, or by handing
+//! the fd to `poll`/`epoll` along with everything else it's waiting on.
+//!
+//! See [DJB's write-up](https://cr.yp.to/docs/selfpipe.html) for the original
+//! description.
+//!
+//! Unlike the original, each signal is its own record, so the pipe can fill
+//! up: it holds 64 KiB by default, which is `64 KiB / size_of::<SignalInfo>()`
+//! (2048) records. The handler can't wait for the reader to catch up, so
+//! anything that doesn't fit is dropped and counted in
+//! [`Receiver::dropped`]. For a standard signal that's harmless, since there's
+//! a record waiting already, but a burst of queued real-time signals can lose
+//! instances that the kernel went to the trouble of keeping separate.
+
+use std::io;
+use std::marker::PhantomData;
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+use std::time::Duration;
+
+use libc::c_int;
+
+use crate::fd;
+use crate::sigaction::{self, Action, Disposition, Error, Flags, Handler, InfoHandler};
+use crate::siginfo::SignalInfo;
+use crate::signal::Signal;
+
+/// Write ends of the pipes, indexed by signal number. `-1` means that nobody
+/// is listening for that signal.
+static WRITE_FDS: [AtomicI32; 65] = [const { AtomicI32::new(-1) }; 65];
+
+/// How many records for each signal didn't fit in the pipe.
+static DROPPED: [AtomicU64; 65] = [const { AtomicU64::new(0) }; 65];
+
+/// What a [`Receiver`] hands out: either just the [`Signal`], or the whole
+/// [`SignalInfo`].
+pub trait Message: Copy {
+    #[doc(hidden)]
+    fn decode(info: SignalInfo) -> Self;
+}
+
+impl Message for Signal {
+    fn decode(info: SignalInfo) -> Signal {
+        info.signal
+    }
+}
+
+impl Message for SignalInfo {
+    fn decode(info: SignalInfo) -> SignalInfo {
+        info
+    }
+}
+
+extern "C" fn on_signal(_signo: c_int, info: *mut libc::siginfo_t, _context: *mut libc::c_void) {
+    fd::preserving_errno(|| {
+        if let Some(info) = unsafe { info.as_ref() }.and_then(SignalInfo::from_siginfo) {
+            forward(&info);
+        }
+    })
+}
+
+/// Writes `info` to whichever pipe is listening for its signal, if any. This
+/// is the whole of our handler, pulled out so that other handlers in the
+/// crate can feed the same channels. Callers are responsible for preserving
+/// `errno`.
+pub(crate) fn forward(info: &SignalInfo) {
+    if let Some(slot) = WRITE_FDS.get(info.signal.as_raw() as usize) {
+        let fd = slot.load(Ordering::Acquire);
+        if fd >= 0 {
+            // Records are well under PIPE_BUF, so the write is atomic:
+            // readers never see half of one. If the pipe is full the record
+            // is lost, and all we can do is count it.
+            let written = unsafe {
+                libc::write(
+                    fd,
+                    info as *const SignalInfo as *const libc::c_void,
+                    mem::size_of::<SignalInfo>(),
+                )
+            };
+            if written < 0 {
+                DROPPED[info.signal.as_raw() as usize].fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// The read end of a self-pipe. Dropping it puts the previous handlers back.
+#[derive(Debug)]
+pub struct Receiver<T: Message = Signal> {
+    read: OwnedFd,
+    write: OwnedFd,
+    registered: Vec<(Signal, Disposition)>,
+    _message: PhantomData<T>,
+}
+
+/// Starts delivering `signals` through a new pipe.
+///
+/// Each signal can only be owned by one `Receiver` at a time; asking for one
+/// that's already taken gives [`Error::Registered`].
+pub fn channel<T: Message>(signals: &[Signal]) -> Result<Receiver<T>, Error> {
+    channel_with(signals, on_signal)
+}
+
+/// Like [`channel`], but with a different handler in front of the pipe. The
+/// handler should call [`forward`] for anything it wants to pass on.
+pub(crate) fn channel_with<T: Message>(
+    signals: &[Signal],
+    handler: InfoHandler,
+) -> Result<Receiver<T>, Error> {
+    let mut fds = [-1; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let (read, write) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+    let mut receiver = Receiver {
+        read,
+        write,
+        registered: Vec::with_capacity(signals.len()),
+        _message: PhantomData,
+    };
+    for &signal in signals {
+        receiver.register(signal, handler)?;
+    }
+    Ok(receiver)
+}
+
+impl<T: Message> Receiver<T> {
+    fn register(&mut self, signal: Signal, handler: InfoHandler) -> Result<(), Error> {
+        if !signal.is_catchable() {
+            return Err(Error::Uncatchable(signal));
+        }
+        let slot = &WRITE_FDS[signal.as_raw() as usize];
+        if slot
+            .compare_exchange(
+                -1,
+                self.write.as_raw_fd(),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            )
+            .is_err()
+        {
+            return Err(Error::Registered(signal));
+        }
+        DROPPED[signal.as_raw() as usize].store(0, Ordering::Relaxed);
+        let action = Action::new(Handler::Info(handler)).flags(Flags::RESTART);
+        match unsafe { sigaction::install(signal, &action) } {
+            Ok(previous) => {
+                self.registered.push((signal, previous));
+                Ok(())
+            }
+            Err(e) => {
+                slot.store(-1, Ordering::Release);
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns the next signal if one has already arrived.
+    pub fn try_recv(&self) -> io::Result<Option<T>> {
+        let mut info = mem::MaybeUninit::<SignalInfo>::uninit();
+        loop {
+            let n = unsafe {
+                libc::read(
+                    self.read.as_raw_fd(),
+                    info.as_mut_ptr() as *mut libc::c_void,
+                    mem::size_of::<SignalInfo>(),
+                )
+            };
+            if n < 0 {
+                let e = io::Error::last_os_error();
+                match e.kind() {
+                    io::ErrorKind::Interrupted => continue,
+                    io::ErrorKind::WouldBlock => return Ok(None),
+                    _ => return Err(e),
+                }
+            }
+            if n as usize != mem::size_of::<SignalInfo>() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "short read from signal pipe",
+                ));
+            }
+            // Only our handler has the write end, and it only ever writes
+            // whole, valid records.
+            return Ok(Some(T::decode(unsafe { info.assume_init() })));
+        }
+    }
+
+    /// Blocks until a signal arrives.
+    pub fn recv(&self) -> io::Result<T> {
+        loop {
+            if let Some(message) = self.try_recv()? {
+                return Ok(message);
+            }
+            fd::wait_readable(self.read.as_raw_fd(), None)?;
+        }
+    }
+
+    /// Blocks until a signal arrives or `timeout` has passed, whichever comes
+    /// first.
+    pub fn recv_timeout(&self, timeout: Duration) -> io::Result<Option<T>> {
+        let deadline = fd::deadline(timeout);
+        loop {
+            if let Some(message) = self.try_recv()? {
+                return Ok(Some(message));
+            }
+            if fd::expired(deadline) {
+                return Ok(None);
+            }
+            fd::wait_readable(self.read.as_raw_fd(), fd::remaining(deadline))?;
+        }
+    }
+
+    /// How many signals have been lost because the pipe was full when they
+    /// arrived. See the [module docs](self).
+    pub fn dropped(&self) -> u64 {
+        self.signals()
+            .map(|signal| DROPPED[signal.as_raw() as usize].load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// The signals this receiver is listening for.
+    pub fn signals(&self) -> impl Iterator<Item = Signal> + '_ {
+        self.registered.iter().map(|(signal, _)| *signal)
+    }
+}
+
+impl<T: Message> AsRawFd for Receiver<T> {
+    /// The read end of the pipe, for use with `poll`, `epoll` and friends.
+    /// It's non-blocking, so use [`Receiver::try_recv`] once it's readable.
+    fn as_raw_fd(&self) -> RawFd {
+        self.read.as_raw_fd()
+    }
+}
+
+impl<T: Message> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        for (signal, previous) in self.registered.drain(..).rev() {
+            let _ = sigaction::restore(signal, &previous);
+            WRITE_FDS[signal.as_raw() as usize].store(-1, Ordering::Release);
+        }
+    }
+}
@@ -0,0 +1,536 @@
+//! One thread, waiting on signals, timers and fds all at once.
+//!
+//! The `select` section of the post never got finished, but this is where it
+//! was heading. Once signals arrive through an fd, they can go into the same
+//! `epoll` set as everything else a program is waiting for: sockets, pipes,
+//! and timers (which Linux will also hand us as fds, through `timerfd`). One
+//! `epoll_wait` then covers "Ctrl+C, or the job finished, or we ran out of
+//! time" without any threads or an async runtime.
+//!
+//! Everything registered gets a [`Token`]. Events can either be handled by a
+//! callback attached to the token, or collected from [`Reactor::poll`].
+//!
+//! The [`future`](crate::future) API waits on its fds here too, through a
+//! [`Handle`]: rather than a token, each fd gets `Waker`s, which are woken
+//! (once) the next time the reactor sees it become readable.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use example::pipe;
+//! use example::reactor::{Event, Reactor};
+//! use example::signal::Signal;
+//!
+//! let mut reactor = Reactor::new()?;
+//! let interrupts = reactor.add_signals(pipe::channel::<Signal>(&[Signal::INT])?)?;
+//! let deadline = reactor.add_timer(Duration::from_secs(10), None)?;
+//! for event in reactor.poll(None)? {
+//!     if event.token() == interrupts {
+//!         println!("interrupted");
+//!     } else if event.token() == deadline {
+//!         println!("out of time");
+//!     }
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::mem;
+use std::ops::BitOr;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, OnceLock};
+use std::task::Waker;
+use std::thread;
+use std::time::Duration;
+
+use crate::fd;
+use crate::signal::Signal;
+use crate::source::Source;
+
+/// How many events to ask `epoll_wait` for at a time.
+const EVENTS_PER_WAIT: usize = 64;
+
+/// Set in the epoll data for fds registered through a [`Handle`], which
+/// carry the fd rather than a [`Token`].
+const WAKER: u64 = 1 << 63;
+
+/// Identifies something registered with a [`Reactor`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Token(pub u64);
+
+/// What to wait for on an fd.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Interest(u32);
+
+impl Interest {
+    pub const READABLE: Interest = Interest(libc::EPOLLIN as u32);
+    pub const WRITABLE: Interest = Interest(libc::EPOLLOUT as u32);
+}
+
+impl BitOr for Interest {
+    type Output = Interest;
+
+    fn bitor(self, rhs: Interest) -> Interest {
+        Interest(self.0 | rhs.0)
+    }
+}
+
+/// What an fd is ready for.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Readiness {
+    pub readable: bool,
+    pub writable: bool,
+    /// The other end hung up, or there's an error waiting to be picked up.
+    /// Reading or writing will say which.
+    pub closed: bool,
+}
+
+impl Readiness {
+    fn from_epoll(events: u32) -> Readiness {
+        Readiness {
+            readable: events & libc::EPOLLIN as u32 != 0,
+            writable: events & libc::EPOLLOUT as u32 != 0,
+            closed: events & (libc::EPOLLHUP | libc::EPOLLERR | libc::EPOLLRDHUP) as u32 != 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// An fd added with [`Reactor::add`] is ready.
+    Ready(Token, Readiness),
+    /// A timer went off, this many times since it was last reported.
+    Timer(Token, u64),
+    /// A signal arrived on a source added with [`Reactor::add_signals`].
+    Signal(Token, Signal),
+}
+
+impl Event {
+    pub fn token(&self) -> Token {
+        match *self {
+            Event::Ready(token, _) | Event::Timer(token, _) | Event::Signal(token, _) => token,
+        }
+    }
+}
+
+/// Whether [`Reactor::run`] should keep going after a callback.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Stop,
+}
+
+type Callback = Box<dyn FnMut(Event) -> Flow>;
+
+enum Kind {
+    /// Someone else's fd; they close it.
+    Fd(RawFd),
+    Timer(OwnedFd),
+    Signals(Box<dyn Source>),
+}
+
+impl Kind {
+    fn fd(&self) -> RawFd {
+        match self {
+            Kind::Fd(fd) => *fd,
+            Kind::Timer(fd) => fd.as_raw_fd(),
+            Kind::Signals(source) => source.as_raw_fd(),
+        }
+    }
+}
+
+struct Entry {
+    kind: Kind,
+    callback: Option<Callback>,
+}
+
+/// The parts of a reactor that a [`Handle`] can reach from other threads.
+struct Shared {
+    epoll: OwnedFd,
+    wakers: Mutex<HashMap<RawFd, Vec<Waker>>>,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, HashMap<RawFd, Vec<Waker>>> {
+        self.wakers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Takes the wakers for `fd`, so that they can be woken without the
+    /// lock held.
+    fn take(&self, fd: RawFd) -> Vec<Waker> {
+        self.lock().remove(&fd).unwrap_or_default()
+    }
+}
+
+pub struct Reactor {
+    shared: Arc<Shared>,
+    entries: HashMap<Token, Entry>,
+    next: u64,
+    /// A read that failed partway through a batch, held back until the rest
+    /// of the batch has been dealt with.
+    failed: Option<io::Error>,
+}
+
+impl fmt::Debug for Reactor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut tokens: Vec<&Token> = self.entries.keys().collect();
+        tokens.sort();
+        f.debug_struct("Reactor")
+            .field("epoll", &self.shared.epoll)
+            .field("tokens", &tokens)
+            .finish()
+    }
+}
+
+impl Reactor {
+    pub fn new() -> io::Result<Reactor> {
+        let raw = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        if raw < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Reactor {
+            shared: Arc::new(Shared {
+                epoll: unsafe { OwnedFd::from_raw_fd(raw) },
+                wakers: Mutex::new(HashMap::new()),
+            }),
+            entries: HashMap::new(),
+            next: 0,
+            failed: None,
+        })
+    }
+
+    /// A handle for registering wakers from anywhere, as long as something
+    /// keeps polling the reactor.
+    pub(crate) fn handle(&self) -> Handle {
+        Handle(Arc::clone(&self.shared))
+    }
+
+    fn insert(&mut self, kind: Kind, interest: Interest) -> io::Result<Token> {
+        let token = Token(self.next);
+        let mut event = libc::epoll_event {
+            events: interest.0,
+            u64: token.0,
+        };
+        let added = unsafe {
+            libc::epoll_ctl(
+                self.shared.epoll.as_raw_fd(),
+                libc::EPOLL_CTL_ADD,
+                kind.fd(),
+                &mut event,
+            )
+        };
+        if added < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.next += 1;
+        self.entries.insert(
+            token,
+            Entry {
+                kind,
+                callback: None,
+            },
+        );
+        Ok(token)
+    }
+
+    /// Watches an fd. It's still ours to close, but it needs to stay open
+    /// until it's been [removed](Reactor::remove).
+    ///
+    /// Readiness is level-triggered: as long as the fd is ready, every poll
+    /// reports it again.
+    pub fn add(&mut self, fd: RawFd, interest: Interest) -> io::Result<Token> {
+        self.insert(Kind::Fd(fd), interest)
+    }
+
+    /// Starts a timer that goes off `after` from now, and then every
+    /// `interval` after that if there is one. A zero `after` would disarm the
+    /// timer, so it's rounded up to a nanosecond.
+    pub fn add_timer(&mut self, after: Duration, interval: Option<Duration>) -> io::Result<Token> {
+        let timer = fd::timerfd()?;
+        fd::set_timer(
+            timer.as_raw_fd(),
+            after.max(Duration::from_nanos(1)),
+            interval,
+        )?;
+        self.insert(Kind::Timer(timer), Interest::READABLE)
+    }
+
+    /// Watches a signal source, which the reactor takes ownership of. Every
+    /// signal that arrives is its own [`Event::Signal`].
+    pub fn add_signals<S: Source + 'static>(&mut self, signals: S) -> io::Result<Token> {
+        self.insert(Kind::Signals(Box::new(signals)), Interest::READABLE)
+    }
+
+    /// Hands events for `token` to `callback` rather than returning them from
+    /// [`Reactor::poll`].
+    pub fn on<F>(&mut self, token: Token, callback: F) -> io::Result<()>
+    where
+        F: FnMut(Event) -> Flow + 'static,
+    {
+        let entry = self
+            .entries
+            .get_mut(&token)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+        entry.callback = Some(Box::new(callback));
+        Ok(())
+    }
+
+    /// Stops watching whatever `token` stands for. Timers and signal sources
+    /// are dropped.
+    pub fn remove(&mut self, token: Token) -> io::Result<()> {
+        let entry = self
+            .entries
+            .remove(&token)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+        let removed = unsafe {
+            libc::epoll_ctl(
+                self.shared.epoll.as_raw_fd(),
+                libc::EPOLL_CTL_DEL,
+                entry.kind.fd(),
+                std::ptr::null_mut(),
+            )
+        };
+        if removed < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Waits up to `timeout` (or forever) for something to happen, runs the
+    /// callbacks for it, and returns the events that didn't have one. Like
+    /// `epoll_wait`, this can return early with nothing if a signal
+    /// interrupts the wait.
+    ///
+    /// If reading a timer or a signal source fails, the rest of the batch is
+    /// still handled and returned, and the error comes back from the next
+    /// call instead.
+    pub fn poll(&mut self, timeout: Option<Duration>) -> io::Result<Vec<Event>> {
+        Ok(self.dispatch(timeout)?.0)
+    }
+
+    /// Runs callbacks until one of them returns [`Flow::Stop`], or there's
+    /// nothing left to wait for. Events without a callback are dropped, so
+    /// anything registered with [`Reactor::add`] needs one, or it'll keep
+    /// coming back for as long as it's ready.
+    pub fn run(&mut self) -> io::Result<()> {
+        while !self.is_empty() {
+            if self.dispatch(None)?.1 == Flow::Stop {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn dispatch(&mut self, timeout: Option<Duration>) -> io::Result<(Vec<Event>, Flow)> {
+        if let Some(e) = self.failed.take() {
+            return Err(e);
+        }
+        let mut ready: [libc::epoll_event; EVENTS_PER_WAIT] = unsafe { mem::zeroed() };
+        let n = unsafe {
+            libc::epoll_wait(
+                self.shared.epoll.as_raw_fd(),
+                ready.as_mut_ptr(),
+                EVENTS_PER_WAIT as libc::c_int,
+                fd::poll_timeout(timeout),
+            )
+        };
+        if n < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                return Ok((Vec::new(), Flow::Continue));
+            }
+            return Err(e);
+        }
+
+        let mut unhandled = Vec::new();
+        let mut flow = Flow::Continue;
+        for ready in &ready[..n as usize] {
+            if ready.u64 & WAKER != 0 {
+                for waker in self.shared.take((ready.u64 & !WAKER) as RawFd) {
+                    waker.wake();
+                }
+                continue;
+            }
+            let token = Token(ready.u64);
+            // Callbacks can't get at the reactor, so nothing is removed
+            // while we're in here; anything epoll reports is still ours.
+            let entry = match self.entries.get_mut(&token) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let events = match &entry.kind {
+                Kind::Fd(_) => vec![Event::Ready(token, Readiness::from_epoll(ready.events))],
+                Kind::Timer(timer) => match fd::read_counter(timer.as_raw_fd()) {
+                    Ok(0) => continue,
+                    Ok(expirations) => vec![Event::Timer(token, expirations)],
+                    Err(e) => {
+                        self.failed.get_or_insert(e);
+                        continue;
+                    }
+                },
+                Kind::Signals(source) => {
+                    // Signals that were read before a failure are gone from
+                    // the source, so they have to be passed on regardless.
+                    let mut signals = Vec::new();
+                    loop {
+                        match source.try_next() {
+                            Ok(Some(signal)) => signals.push(Event::Signal(token, signal)),
+                            Ok(None) => break,
+                            Err(e) => {
+                                self.failed.get_or_insert(e);
+                                break;
+                            }
+                        }
+                    }
+                    signals
+                }
+            };
+            match &mut entry.callback {
+                Some(callback) => {
+                    for event in events {
+                        if callback(event) == Flow::Stop {
+                            flow = Flow::Stop;
+                        }
+                    }
+                }
+                None => unhandled.extend(events),
+            }
+        }
+        Ok((unhandled, flow))
+    }
+}
+
+impl AsRawFd for Reactor {
+    /// The epoll fd, which is itself readable whenever there's an event
+    /// waiting. That lets one reactor be nested inside another, or inside a
+    /// plain `poll`.
+    fn as_raw_fd(&self) -> RawFd {
+        self.shared.epoll.as_raw_fd()
+    }
+}
+
+/// Registers wakers with a [`Reactor`], from any thread.
+#[derive(Clone)]
+pub(crate) struct Handle(Arc<Shared>);
+
+impl fmt::Debug for Handle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Handle").field(&self.0.epoll).finish()
+    }
+}
+
+/// A reactor on a thread of its own, for futures polled outside of an
+/// [`Executor`](crate::executor::Executor). Started the first time it's
+/// needed, and left running for the rest of the process.
+static BACKGROUND: OnceLock<Handle> = OnceLock::new();
+static STARTING: Mutex<()> = Mutex::new(());
+
+thread_local! {
+    /// The reactor of whatever is polling futures on this thread, if it's
+    /// also going to be the one waiting on it.
+    static CURRENT: RefCell<Option<Handle>> = const { RefCell::new(None) };
+}
+
+/// Makes a reactor [current](Handle::current) until it's dropped.
+pub(crate) struct Enter(Option<Handle>);
+
+impl Drop for Enter {
+    fn drop(&mut self) {
+        CURRENT.with(|current| *current.borrow_mut() = self.0.take());
+    }
+}
+
+impl Handle {
+    /// The reactor that futures polled on this thread should wait on: the
+    /// one [entered](Handle::enter) on this thread, or else one running in
+    /// the background.
+    pub(crate) fn current() -> io::Result<Handle> {
+        if let Some(handle) = CURRENT.with(|current| current.borrow().clone()) {
+            return Ok(handle);
+        }
+        if let Some(handle) = BACKGROUND.get() {
+            return Ok(handle.clone());
+        }
+        let _starting = STARTING.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(handle) = BACKGROUND.get() {
+            return Ok(handle.clone());
+        }
+        // Reactors can hold things that aren't Send, so this one has to be
+        // made on the thread that runs it.
+        let (tx, rx) = mpsc::channel();
+        thread::Builder::new()
+            .name("signal-wakers".into())
+            .spawn(move || {
+                let mut reactor = match Reactor::new() {
+                    Ok(reactor) => reactor,
+                    Err(e) => {
+                        let _ = tx.send(Err(e));
+                        return;
+                    }
+                };
+                let _ = tx.send(Ok(reactor.handle()));
+                while reactor.poll(None).is_ok() {}
+            })?;
+        let handle = rx
+            .recv()
+            .map_err(|_| io::Error::other("the reactor thread died"))??;
+        Ok(BACKGROUND.get_or_init(|| handle).clone())
+    }
+
+    /// Makes this the reactor that futures polled on this thread wait on,
+    /// until the guard is dropped. Whoever does this should be polling the
+    /// reactor whenever they're not polling futures.
+    pub(crate) fn enter(&self) -> Enter {
+        Enter(CURRENT.with(|current| current.replace(Some(self.clone()))))
+    }
+
+    /// Wakes `waker` once `fd` is readable, which may be straight away. Like
+    /// a waker, the registration is used up once it fires.
+    ///
+    /// The fd shouldn't also be [added](Reactor::add) to the same reactor.
+    pub(crate) fn wake_when_readable(&self, fd: RawFd, waker: &Waker) -> io::Result<()> {
+        let mut wakers = self.0.lock();
+        let waiting = wakers.entry(fd).or_default();
+        if !waiting.iter().any(|w| w.will_wake(waker)) {
+            waiting.push(waker.clone());
+        }
+        // Re-arm, or add it if this is the first time we've seen it.
+        match self.ctl(libc::EPOLL_CTL_MOD, fd) {
+            Err(e) if e.raw_os_error() == Some(libc::ENOENT) => self.ctl(libc::EPOLL_CTL_ADD, fd),
+            result => result,
+        }
+    }
+
+    /// Stops watching `fd`. Must be called before it's closed, since the
+    /// number may be reused for something else.
+    pub(crate) fn forget(&self, fd: RawFd) {
+        let mut wakers = self.0.lock();
+        wakers.remove(&fd);
+        unsafe {
+            libc::epoll_ctl(
+                self.0.epoll.as_raw_fd(),
+                libc::EPOLL_CTL_DEL,
+                fd,
+                std::ptr::null_mut(),
+            )
+        };
+    }
+
+    fn ctl(&self, op: libc::c_int, fd: RawFd) -> io::Result<()> {
+        let mut event = libc::epoll_event {
+            events: (libc::EPOLLIN | libc::EPOLLONESHOT) as u32,
+            u64: WAKER | fd as u64,
+        };
+        if unsafe { libc::epoll_ctl(self.0.epoll.as_raw_fd(), op, fd, &mut event) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
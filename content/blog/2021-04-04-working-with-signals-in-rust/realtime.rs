@@ -0,0 +1,111 @@
+//! Real-time signals: numbered at runtime, queued rather than merged, and
+//! able to carry a payload.
+//!
+//! If a standard signal arrives while an earlier one is still pending, the
+//! two are merged and the handler runs once. Real-time signals (`SIGRTMIN`
+//! to `SIGRTMAX`) are queued instead, so every one that's sent is delivered,
+//! in order, along with the `sigval` it was sent with. Through a
+//! [`pipe::Receiver<SignalInfo>`](crate::pipe::Receiver) each of them shows
+//! up as its own message, as long as the pipe keeps up: a burst of more than
+//! about 2048 between reads overflows it, and the overflow is only counted
+//! (see [`Receiver::dropped`](crate::pipe::Receiver::dropped)). A
+//! [`SignalFd`](crate::signalfd::SignalFd) reads them straight off the
+//! kernel's queue instead, and has no such limit.
+//!
+//! There's no fixed meaning for any of the real-time signals, so rather than
+//! hard-coding numbers (and colliding with some library that had the same
+//! idea), we hand them out at runtime.
+
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use libc::{c_int, pid_t};
+
+use crate::sigaction::{self, Handler};
+use crate::signal::Signal;
+use crate::sigset::SigSet;
+
+extern "C" {
+    // Not in the version of libc we're using.
+    fn sigqueue(pid: pid_t, sig: c_int, value: libc::sigval) -> c_int;
+}
+
+/// Real-time signals that are currently handed out.
+static ALLOCATED: AtomicU64 = AtomicU64::new(0);
+
+/// A real-time signal that's ours until this is dropped.
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct Allocation {
+    signal: Signal,
+}
+
+impl Allocation {
+    pub fn signal(&self) -> Signal {
+        self.signal
+    }
+}
+
+impl Drop for Allocation {
+    fn drop(&mut self) {
+        ALLOCATED.fetch_and(!SigSet::from(self.signal).bits(), Ordering::AcqRel);
+    }
+}
+
+/// Reserves a real-time signal that nothing else in the process is using.
+///
+/// A signal counts as in use if we've already handed it out, or if it has
+/// anything other than the default disposition (which is how we avoid
+/// treading on other libraries). `SIGRTMAX` is never handed out: the crate
+/// keeps it for itself.
+pub fn allocate() -> Option<Allocation> {
+    let first = Signal::rt_min().as_raw();
+    let last = Signal::rt_max().as_raw() - 1;
+    for signal in (first..=last).filter_map(Signal::from_raw) {
+        let bit = SigSet::from(signal).bits();
+        if ALLOCATED.fetch_or(bit, Ordering::AcqRel) & bit != 0 {
+            continue;
+        }
+        match sigaction::current(signal).map(|d| d.handler()) {
+            Ok(Handler::Default) => return Some(Allocation { signal }),
+            _ => {
+                ALLOCATED.fetch_and(!bit, Ordering::AcqRel);
+            }
+        }
+    }
+    None
+}
+
+fn queue(pid: pid_t, signal: Signal, value: libc::sigval) -> io::Result<()> {
+    if unsafe { sigqueue(pid, signal.as_raw(), value) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Sends `signal` to `pid` with an integer payload, which the receiver can
+/// get at with [`SignalInfo::int_value`](crate::siginfo::SignalInfo::int_value).
+///
+/// Fails with `EAGAIN` if the receiver already has as many signals queued as
+/// `RLIMIT_SIGPENDING` allows.
+pub fn queue_int(pid: pid_t, signal: Signal, value: i32) -> io::Result<()> {
+    queue(
+        pid,
+        signal,
+        libc::sigval {
+            sival_ptr: value as usize as *mut libc::c_void,
+        },
+    )
+}
+
+/// Sends `signal` to `pid` with a pointer-sized payload, which turns up in
+/// [`SignalInfo::value`](crate::siginfo::SignalInfo::value). Only meaningful
+/// as a pointer if the receiver is this process.
+pub fn queue_ptr(pid: pid_t, signal: Signal, value: usize) -> io::Result<()> {
+    queue(
+        pid,
+        signal,
+        libc::sigval {
+            sival_ptr: value as *mut libc::c_void,
+        },
+    )
+}
@@ -0,0 +1,72 @@
+//! Handlers that only last as long as a scope.
+//!
+//! This is about as close as we can get to the `__try` / `__except` blocks
+//! of Windows' Structured Exception Handling: install a handler, run some
+//! code, and put back whatever was there before - exactly as it was, flags
+//! and mask included - on the way out, even if the code panics. Libraries
+//! can use this to change how a signal is handled for a while without
+//! clobbering the application's own handlers.
+//!
+//! Scopes nest, as long as they're unwound in the order they were entered.
+//! Guards can't be sent to other threads, which rules out the easiest ways
+//! of getting that wrong.
+
+use std::marker::PhantomData;
+
+use crate::sigaction::{self, Action, Disposition, Error};
+use crate::signal::Signal;
+
+/// Puts the previous disposition back when dropped.
+#[derive(Debug)]
+#[must_use = "the handler is uninstalled as soon as the guard is dropped"]
+pub struct HandlerGuard {
+    signal: Signal,
+    previous: Disposition,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl HandlerGuard {
+    pub fn signal(&self) -> Signal {
+        self.signal
+    }
+
+    /// The disposition that will be restored.
+    pub fn previous(&self) -> &Disposition {
+        &self.previous
+    }
+}
+
+impl Drop for HandlerGuard {
+    fn drop(&mut self) {
+        let _ = sigaction::restore(self.signal, &self.previous);
+    }
+}
+
+/// Installs `action` for `signal` until the returned guard is dropped.
+///
+/// # Safety
+///
+/// As for [`sigaction::install`]: the handler must be async-signal-safe.
+pub unsafe fn install<A: Into<Action>>(signal: Signal, action: A) -> Result<HandlerGuard, Error> {
+    let previous = sigaction::install(signal, &action.into())?;
+    Ok(HandlerGuard {
+        signal,
+        previous,
+        _not_send: PhantomData,
+    })
+}
+
+/// Runs `body` with `action` installed for `signal`, then puts the previous
+/// disposition back.
+///
+/// # Safety
+///
+/// As for [`sigaction::install`]: the handler must be async-signal-safe.
+pub unsafe fn with_signal_handler<A, F, R>(signal: Signal, action: A, body: F) -> Result<R, Error>
+where
+    A: Into<Action>,
+    F: FnOnce() -> R,
+{
+    let _guard = install(signal, action)?;
+    Ok(body())
+}
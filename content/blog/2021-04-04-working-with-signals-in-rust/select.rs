@@ -0,0 +1,150 @@
+//! Waiting on signals, channels and deadlines together.
+//!
+//! The post suggests `crossbeam::select!` for this, which works nicely once
+//! the signals have been turned into a crossbeam channel by a thread of their
+//! own. Everything in this crate that can be waited on is already an fd,
+//! though, so we can skip the extra thread and go straight to `poll`.
+//!
+//! A [`Select`] is built up out of cases, each of which gets an index. Waiting
+//! returns the index of a case that's ready; it's then up to the caller to
+//! take the message or signal from whatever that case was watching.
+//!
+//! ```no_run
+//! use std::thread;
+//! use std::time::Duration;
+//!
+//! use example::channel;
+//! use example::pipe;
+//! use example::select::Select;
+//! use example::signal::Signal;
+//!
+//! let interrupts = pipe::channel::<Signal>(&[Signal::INT])?;
+//! let (done_tx, done_rx) = channel::channel()?;
+//! thread::spawn(move || done_tx.send("finished"));
+//!
+//! let mut select = Select::new();
+//! let interrupted = select.recv(&interrupts);
+//! let done = select.recv(&done_rx);
+//! let timeout = select.timeout(Duration::from_secs(10));
+//! match select.wait()? {
+//!     i if i == interrupted => println!("got {:?}", interrupts.try_recv()?),
+//!     i if i == done => println!("{:?}", done_rx.try_recv()),
+//!     i if i == timeout => println!("out of time"),
+//!     _ => unreachable!(),
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::io;
+use std::marker::PhantomData;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+use crate::fd;
+
+enum Case {
+    Readable(RawFd),
+    /// `None` never comes: it's a timeout too long to have a deadline.
+    Deadline(Option<Instant>),
+}
+
+/// A set of things to wait for, any one of which will do.
+///
+/// The fds being watched are borrowed for as long as the `Select` lives, so
+/// that they can't be closed out from under it.
+pub struct Select<'a> {
+    cases: Vec<Case>,
+    _borrowed: PhantomData<&'a ()>,
+}
+
+impl Default for Select<'_> {
+    fn default() -> Self {
+        Select {
+            cases: Vec::new(),
+            _borrowed: PhantomData,
+        }
+    }
+}
+
+impl<'a> Select<'a> {
+    pub fn new() -> Select<'a> {
+        Select::default()
+    }
+
+    /// Adds a case that's ready when `source` has something to read: a signal
+    /// [`Receiver`](crate::pipe::Receiver) or [`SignalFd`](crate::signalfd::SignalFd),
+    /// a [`channel::Receiver`](crate::channel::Receiver), or any other fd.
+    pub fn recv<S: AsRawFd + ?Sized>(&mut self, source: &'a S) -> usize {
+        self.cases.push(Case::Readable(source.as_raw_fd()));
+        self.cases.len() - 1
+    }
+
+    /// Adds a case that's ready once `deadline` has passed.
+    pub fn deadline(&mut self, deadline: Instant) -> usize {
+        self.cases.push(Case::Deadline(Some(deadline)));
+        self.cases.len() - 1
+    }
+
+    /// Adds a case that's ready `timeout` from now.
+    pub fn timeout(&mut self, timeout: Duration) -> usize {
+        self.cases.push(Case::Deadline(fd::deadline(timeout)));
+        self.cases.len() - 1
+    }
+
+    /// Returns the index of a ready case without waiting, if there is one.
+    pub fn try_select(&self) -> io::Result<Option<usize>> {
+        self.select(Some(Duration::from_secs(0)))
+    }
+
+    /// Waits until a case is ready and returns its index. If more than one is
+    /// ready, the one that was added first wins.
+    pub fn wait(&self) -> io::Result<usize> {
+        loop {
+            let earliest = self
+                .cases
+                .iter()
+                .filter_map(|case| match case {
+                    Case::Deadline(deadline) => *deadline,
+                    Case::Readable(_) => None,
+                })
+                .min();
+            if let Some(i) = self.select(fd::remaining(earliest))? {
+                return Ok(i);
+            }
+        }
+    }
+
+    /// Polls for up to `timeout`, then reports the first case that's ready.
+    /// Returns early (with `None`) if a signal interrupts the wait.
+    fn select(&self, timeout: Option<Duration>) -> io::Result<Option<usize>> {
+        let mut pollfds: Vec<libc::pollfd> = self
+            .cases
+            .iter()
+            .map(|case| libc::pollfd {
+                fd: match case {
+                    Case::Readable(fd) => *fd,
+                    // poll skips negative fds.
+                    Case::Deadline(_) => -1,
+                },
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect();
+        let n = pollfds.len() as libc::nfds_t;
+        if unsafe { libc::poll(pollfds.as_mut_ptr(), n, fd::poll_timeout(timeout)) } < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+
+        Ok(self
+            .cases
+            .iter()
+            .zip(&pollfds)
+            .position(|(case, pollfd)| match case {
+                Case::Readable(_) => pollfd.revents != 0,
+                Case::Deadline(deadline) => fd::expired(*deadline),
+            }))
+    }
+}
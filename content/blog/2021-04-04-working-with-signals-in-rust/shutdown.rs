@@ -0,0 +1,338 @@
+//! Named shutdown hooks, run in order and against the clock.
+//!
+//! Once we've been asked to stop, there's usually more than one thing to do:
+//! flush buffers, close connections, remove temp files. Some of those have to
+//! happen before others, and none of them should be able to hold up the
+//! shutdown forever. Hooks declare what they need to run after, and each one
+//! gets a deadline of its own; at the end we get a report of what happened.
+
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Hook {
+    name: String,
+    after: Vec<String>,
+    timeout: Duration,
+    run: Box<dyn FnOnce() + Send>,
+}
+
+impl Hook {
+    pub fn new<F>(name: &str, run: F) -> Hook
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        Hook {
+            name: name.to_owned(),
+            after: Vec::new(),
+            timeout: DEFAULT_TIMEOUT,
+            run: Box::new(run),
+        }
+    }
+
+    /// Don't start this hook until `other` has finished (or given up). Hooks
+    /// that were never registered are treated as already finished.
+    pub fn after(mut self, other: &str) -> Hook {
+        self.after.push(other.to_owned());
+        self
+    }
+
+    /// How long to wait for this hook before moving on without it.
+    pub fn timeout(mut self, timeout: Duration) -> Hook {
+        self.timeout = timeout;
+        self
+    }
+}
+
+impl fmt::Debug for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hook")
+            .field("name", &self.name)
+            .field("after", &self.after)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Registry {
+    hooks: Vec<Hook>,
+}
+
+/// The hooks couldn't be put in order, because these ones depend on each
+/// other in a loop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cycle(pub Vec<String>);
+
+impl fmt::Display for Cycle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "shutdown hooks depend on each other: {}",
+            self.0.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for Cycle {}
+
+/// A hook with this name has already been registered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Duplicate(pub String);
+
+impl fmt::Display for Duplicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "there's already a shutdown hook called {:?}", self.0)
+    }
+}
+
+impl std::error::Error for Duplicate {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Completed(Duration),
+    TimedOut,
+    Panicked(String),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub outcomes: Vec<(String, Outcome)>,
+}
+
+impl Report {
+    /// True if every hook ran to completion.
+    pub fn is_clean(&self) -> bool {
+        self.outcomes
+            .iter()
+            .all(|(_, outcome)| matches!(outcome, Outcome::Completed(_)))
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, outcome) in &self.outcomes {
+            match outcome {
+                Outcome::Completed(took) => writeln!(f, "{}: completed in {:?}", name, took)?,
+                Outcome::TimedOut => writeln!(f, "{}: timed out", name)?,
+                Outcome::Panicked(message) => writeln!(f, "{}: panicked: {}", name, message)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    /// Adds a hook. Names are how hooks refer to each other, so each one
+    /// can only be used once.
+    pub fn register(&mut self, hook: Hook) -> Result<&mut Registry, Duplicate> {
+        if self.hooks.iter().any(|h| h.name == hook.name) {
+            return Err(Duplicate(hook.name));
+        }
+        self.hooks.push(hook);
+        Ok(self)
+    }
+
+    /// Runs every hook, one at a time, each after the ones it depends on.
+    /// Hooks with no ordering between them run in the order they were
+    /// registered.
+    ///
+    /// A hook that overruns its timeout is left running in the background;
+    /// there's no way to safely stop a thread from the outside.
+    pub fn run(self) -> Result<Report, Cycle> {
+        let order = self.order()?;
+        let mut hooks: Vec<Option<Hook>> = self.hooks.into_iter().map(Some).collect();
+        let mut report = Report::default();
+        for i in order {
+            let hook = hooks[i].take().expect("each hook is only ordered once");
+            let name = hook.name.clone();
+            report.outcomes.push((name, run_one(hook)));
+        }
+        Ok(report)
+    }
+
+    fn order(&self) -> Result<Vec<usize>, Cycle> {
+        let index: HashMap<&str, usize> = self
+            .hooks
+            .iter()
+            .enumerate()
+            .map(|(i, hook)| (hook.name.as_str(), i))
+            .collect();
+        let mut done = HashSet::new();
+        let mut order = Vec::with_capacity(self.hooks.len());
+        while order.len() < self.hooks.len() {
+            let ready = (0..self.hooks.len()).find(|i| {
+                !done.contains(i)
+                    && self.hooks[*i]
+                        .after
+                        .iter()
+                        .filter_map(|name| index.get(name.as_str()))
+                        .all(|dependency| done.contains(dependency))
+            });
+            match ready {
+                Some(i) => {
+                    done.insert(i);
+                    order.push(i);
+                }
+                None => {
+                    let stuck = (0..self.hooks.len())
+                        .filter(|i| !done.contains(i))
+                        .map(|i| self.hooks[i].name.clone())
+                        .collect();
+                    return Err(Cycle(stuck));
+                }
+            }
+        }
+        Ok(order)
+    }
+}
+
+fn run_one(hook: Hook) -> Outcome {
+    let (tx, rx) = mpsc::channel();
+    let started = Instant::now();
+    let run = hook.run;
+    let spawned = thread::Builder::new()
+        .name(format!("shutdown:{}", hook.name))
+        .spawn(move || {
+            let _ = tx.send(panic::catch_unwind(AssertUnwindSafe(run)));
+        });
+    if let Err(e) = spawned {
+        return Outcome::Panicked(format!("couldn't start a thread for the hook: {}", e));
+    }
+    match rx.recv_timeout(hook.timeout) {
+        Ok(Ok(())) => Outcome::Completed(started.elapsed()),
+        Ok(Err(payload)) => Outcome::Panicked(panic_message(payload)),
+        Err(mpsc::RecvTimeoutError::Timeout) => Outcome::TimedOut,
+        // The sender only goes away without sending if the thread died some
+        // other way than a caught panic.
+        Err(mpsc::RecvTimeoutError::Disconnected) => Outcome::Panicked("hook thread died".into()),
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_owned()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "(no message)".to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    fn names(report: &Report) -> Vec<&str> {
+        report
+            .outcomes
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn runs_hooks_after_their_dependencies() {
+        let ran = Arc::new(Mutex::new(Vec::new()));
+        let hook = |name: &'static str| {
+            let ran = Arc::clone(&ran);
+            Hook::new(name, move || ran.lock().unwrap().push(name))
+        };
+        let mut hooks = Registry::new();
+        hooks
+            .register(hook("close").after("flush"))
+            .unwrap()
+            .register(hook("flush").after("stop"))
+            .unwrap()
+            .register(hook("log"))
+            .unwrap()
+            .register(hook("stop").after("missing"))
+            .unwrap();
+
+        let report = hooks.run().unwrap();
+        assert!(report.is_clean());
+        assert_eq!(names(&report), ["log", "stop", "flush", "close"]);
+        assert_eq!(*ran.lock().unwrap(), ["log", "stop", "flush", "close"]);
+    }
+
+    #[test]
+    fn rejects_duplicate_names() {
+        let mut hooks = Registry::new();
+        hooks.register(Hook::new("flush", || {})).unwrap();
+        let duplicate = hooks.register(Hook::new("flush", || {})).unwrap_err();
+        assert_eq!(duplicate, Duplicate("flush".into()));
+    }
+
+    #[test]
+    fn detects_cycles() {
+        let mut hooks = Registry::new();
+        hooks
+            .register(Hook::new("a", || {}).after("b"))
+            .unwrap()
+            .register(Hook::new("b", || {}).after("a"))
+            .unwrap()
+            .register(Hook::new("c", || {}))
+            .unwrap();
+        assert_eq!(
+            hooks.run().unwrap_err(),
+            Cycle(vec!["a".into(), "b".into()])
+        );
+    }
+
+    #[test]
+    fn gives_up_on_slow_hooks() {
+        let mut hooks = Registry::new();
+        hooks
+            .register(
+                Hook::new("slow", || thread::sleep(Duration::from_secs(5)))
+                    .timeout(Duration::from_millis(50)),
+            )
+            .unwrap()
+            .register(Hook::new("next", || {}).after("slow"))
+            .unwrap();
+
+        let started = Instant::now();
+        let report = hooks.run().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(report.outcomes[0], ("slow".into(), Outcome::TimedOut));
+        assert!(matches!(report.outcomes[1].1, Outcome::Completed(_)));
+        assert!(!report.is_clean());
+    }
+
+    #[test]
+    fn reports_panics() {
+        let mut hooks = Registry::new();
+        hooks
+            .register(Hook::new("str", || panic!("static message")))
+            .unwrap()
+            .register(Hook::new("string", || panic!("{} message", "formatted")))
+            .unwrap()
+            .register(Hook::new("next", || {}))
+            .unwrap();
+
+        let report = hooks.run().unwrap();
+        assert_eq!(
+            report.outcomes[..2],
+            [
+                ("str".into(), Outcome::Panicked("static message".into())),
+                (
+                    "string".into(),
+                    Outcome::Panicked("formatted message".into())
+                ),
+            ]
+        );
+        assert!(matches!(report.outcomes[2].1, Outcome::Completed(_)));
+    }
+}
@@ -0,0 +1,254 @@
+//! Installing signal handlers with `sigaction(2)`.
+//!
+//! `man 3 signal` tells us that new code should use `sigaction` instead of
+//! `signal`, and this is what that looks like. Unlike `signal`, we get to say
+//! exactly what happens while the handler runs (which signals are blocked,
+//! whether interrupted syscalls restart), and we always get the previous
+//! disposition back so that it can be put back later.
+
+use std::fmt;
+use std::io;
+use std::mem;
+use std::ops::BitOr;
+use std::ptr;
+
+use libc::c_int;
+
+use crate::signal::Signal;
+use crate::sigset::SigSet;
+
+/// The `sa_flags` we know how to deal with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct Flags(c_int);
+
+impl Flags {
+    pub const EMPTY: Flags = Flags(0);
+    /// Restart interruptible syscalls once the handler returns, rather than
+    /// failing them with `EINTR`.
+    pub const RESTART: Flags = Flags(libc::SA_RESTART);
+    /// Don't block the signal while its own handler is running.
+    pub const NODEFER: Flags = Flags(libc::SA_NODEFER);
+    /// Go back to `SIG_DFL` as soon as the handler has been entered.
+    pub const RESETHAND: Flags = Flags(libc::SA_RESETHAND);
+
+    pub const fn bits(self) -> c_int {
+        self.0
+    }
+
+    pub const fn contains(self, other: Flags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Flags {
+    type Output = Flags;
+
+    fn bitor(self, rhs: Flags) -> Flags {
+        Flags(self.0 | rhs.0)
+    }
+}
+
+/// What should happen when a signal arrives.
+#[derive(Clone, Copy)]
+pub enum Handler {
+    /// `SIG_DFL`
+    Default,
+    /// `SIG_IGN`
+    Ignore,
+    /// A plain one-argument handler, like `handle_interrupt` in `main`.
+    Function(extern "C" fn(c_int)),
+    /// A three-argument handler, which gets a `siginfo_t` describing the
+    /// signal and the `ucontext_t` of the code it interrupted. Installing one
+    /// of these sets `SA_SIGINFO`.
+    Info(InfoHandler),
+}
+
+pub type InfoHandler = extern "C" fn(c_int, *mut libc::siginfo_t, *mut libc::c_void);
+
+impl fmt::Debug for Handler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Handler::Default => f.write_str("Default"),
+            Handler::Ignore => f.write_str("Ignore"),
+            Handler::Function(h) => write!(f, "Function({:p})", *h as *const ()),
+            Handler::Info(h) => write!(f, "Info({:p})", *h as *const ()),
+        }
+    }
+}
+
+/// Everything needed to install a handler: the handler itself, flags, and the
+/// set of signals that are blocked while it runs (on top of the signal being
+/// handled, unless `NODEFER` is given).
+#[derive(Clone, Debug)]
+pub struct Action {
+    handler: Handler,
+    flags: Flags,
+    mask: SigSet,
+}
+
+impl Action {
+    pub fn new(handler: Handler) -> Action {
+        Action {
+            handler,
+            flags: Flags::EMPTY,
+            mask: SigSet::empty(),
+        }
+    }
+
+    pub fn flags(mut self, flags: Flags) -> Action {
+        self.flags = flags;
+        self
+    }
+
+    pub fn mask<S: Into<SigSet>>(mut self, signals: S) -> Action {
+        self.mask = signals.into();
+        self
+    }
+
+    pub(crate) fn to_raw(&self) -> libc::sigaction {
+        let mut raw: libc::sigaction = unsafe { mem::zeroed() };
+        raw.sa_sigaction = match self.handler {
+            Handler::Default => libc::SIG_DFL,
+            Handler::Ignore => libc::SIG_IGN,
+            Handler::Function(h) => h as *const () as libc::sighandler_t,
+            Handler::Info(h) => h as *const () as libc::sighandler_t,
+        };
+        raw.sa_flags = self.flags.bits();
+        if let Handler::Info(_) = self.handler {
+            raw.sa_flags |= libc::SA_SIGINFO;
+        }
+        raw.sa_mask = self.mask.to_sigset();
+        raw
+    }
+}
+
+impl From<Handler> for Action {
+    fn from(handler: Handler) -> Action {
+        Action::new(handler)
+    }
+}
+
+/// A disposition as the kernel reported it. Holding on to one of these is
+/// enough to put things back exactly as they were with [`restore`].
+#[derive(Clone, Copy)]
+pub struct Disposition {
+    pub(crate) raw: libc::sigaction,
+}
+
+impl Disposition {
+    pub fn handler(&self) -> Handler {
+        match self.raw.sa_sigaction {
+            libc::SIG_DFL => Handler::Default,
+            libc::SIG_IGN => Handler::Ignore,
+            h if self.raw.sa_flags & libc::SA_SIGINFO != 0 => {
+                Handler::Info(unsafe { mem::transmute::<libc::sighandler_t, InfoHandler>(h) })
+            }
+            h => Handler::Function(unsafe {
+                mem::transmute::<libc::sighandler_t, extern "C" fn(c_int)>(h)
+            }),
+        }
+    }
+
+    pub fn flags(&self) -> Flags {
+        Flags(self.raw.sa_flags)
+    }
+
+    pub fn mask(&self) -> SigSet {
+        SigSet::from_sigset(&self.raw.sa_mask)
+    }
+}
+
+impl fmt::Debug for Disposition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Disposition")
+            .field("handler", &self.handler())
+            .field("flags", &self.flags())
+            .field("mask", &self.mask())
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// `SIGKILL` and `SIGSTOP` can't have their disposition changed.
+    Uncatchable(Signal),
+    /// Something else in this crate is already delivering this signal.
+    Registered(Signal),
+    /// The signal was ignored when we got it, and we've been asked to leave
+    /// it that way. See [`inherited`](crate::inherited).
+    Ignored(Signal),
+    /// `sigaction` itself failed.
+    Os(io::Error),
+    /// Changing the signal mask failed, in this thread or in another one.
+    Mask(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Uncatchable(signal) => {
+                write!(f, "{} cannot be caught or ignored", signal)
+            }
+            Error::Registered(signal) => {
+                write!(f, "{} is already being delivered elsewhere", signal)
+            }
+            Error::Ignored(signal) => {
+                write!(f, "{} was inherited as ignored", signal)
+            }
+            Error::Os(e) => write!(f, "sigaction failed: {}", e),
+            Error::Mask(e) => write!(f, "failed to change the signal mask: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Uncatchable(_) | Error::Registered(_) | Error::Ignored(_) => None,
+            Error::Os(e) | Error::Mask(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Os(e)
+    }
+}
+
+pub(crate) fn sigaction(
+    signal: Signal,
+    new: Option<&libc::sigaction>,
+) -> Result<Disposition, Error> {
+    if new.is_some() && !signal.is_catchable() {
+        return Err(Error::Uncatchable(signal));
+    }
+    let mut old: libc::sigaction = unsafe { mem::zeroed() };
+    let new = new.map_or(ptr::null(), |n| n as *const _);
+    if unsafe { libc::sigaction(signal.as_raw(), new, &mut old) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(Disposition { raw: old })
+}
+
+/// Installs `action` for `signal`, handing back whatever was there before.
+///
+/// # Safety
+///
+/// The handler will be run in signal context, interrupting whatever the
+/// thread happened to be doing. It must stick to async-signal-safe functions
+/// (see `man 7 signal-safety`) and must not touch state that the interrupted
+/// code might be halfway through changing.
+pub unsafe fn install(signal: Signal, action: &Action) -> Result<Disposition, Error> {
+    sigaction(signal, Some(&action.to_raw()))
+}
+
+/// Puts back a disposition previously returned by [`install`] or [`current`].
+pub fn restore(signal: Signal, previous: &Disposition) -> Result<Disposition, Error> {
+    sigaction(signal, Some(&previous.raw))
+}
+
+/// Looks at the current disposition without changing it.
+pub fn current(signal: Signal) -> Result<Disposition, Error> {
+    sigaction(signal, None)
+}
@@ -0,0 +1,135 @@
+//! What the kernel tells us about a signal, beyond its number.
+//!
+//! A handler installed with `SA_SIGINFO` gets a `siginfo_t` alongside the
+//! signal number, and so does anyone reading a signalfd. That's where to look
+//! to find out who sent a `SIGTERM`, or whether a `SIGINT` came from the
+//! terminal or from `kill`.
+
+use libc::{c_int, pid_t, uid_t};
+
+use crate::signal::Signal;
+
+// These aren't in the version of libc we're using.
+const SI_USER: c_int = 0;
+const SI_KERNEL: c_int = 0x80;
+const SI_QUEUE: c_int = -1;
+const SI_TIMER: c_int = -2;
+const SI_TKILL: c_int = -6;
+
+/// Where a signal came from, as far as `si_code` can tell us.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Code {
+    /// `kill(2)`, from `si_pid` running as `si_uid`.
+    User,
+    /// `tgkill(2)`: `raise`, `pthread_kill` and friends.
+    Tkill,
+    /// Generated by the terminal driver: `Ctrl+C`, `Ctrl+\` or `Ctrl+Z`.
+    Tty,
+    /// Generated by the kernel for some other reason.
+    Kernel,
+    /// `sigqueue(3)`, with a payload in `si_value`.
+    Queue,
+    /// A POSIX timer expired.
+    Timer,
+    /// Anything else, including the signal-specific codes that go with
+    /// faults and `SIGCHLD`.
+    Other(c_int),
+}
+
+impl Code {
+    fn new(signal: Signal, code: c_int) -> Code {
+        match code {
+            SI_USER => Code::User,
+            SI_TKILL => Code::Tkill,
+            // The kernel only sends these of its own accord on behalf of the
+            // terminal.
+            SI_KERNEL if [Signal::INT, Signal::QUIT, Signal::TSTP].contains(&signal) => Code::Tty,
+            SI_KERNEL => Code::Kernel,
+            SI_QUEUE => Code::Queue,
+            SI_TIMER => Code::Timer,
+            other => Code::Other(other),
+        }
+    }
+
+    pub fn as_raw(self) -> c_int {
+        match self {
+            Code::User => SI_USER,
+            Code::Tkill => SI_TKILL,
+            Code::Tty | Code::Kernel => SI_KERNEL,
+            Code::Queue => SI_QUEUE,
+            Code::Timer => SI_TIMER,
+            Code::Other(code) => code,
+        }
+    }
+
+    /// Whether `si_pid` and `si_uid` identify a sending process.
+    pub fn has_sender(self) -> bool {
+        matches!(self, Code::User | Code::Tkill | Code::Queue)
+    }
+}
+
+/// The useful parts of a `siginfo_t`, in a form that can be copied around and
+/// sent between threads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SignalInfo {
+    pub signal: Signal,
+    pub code: Code,
+    /// The sending process, when [`Code::has_sender`] says there is one.
+    pub pid: pid_t,
+    pub uid: uid_t,
+    /// The `sigval` payload. Only meaningful for [`Code::Queue`] and
+    /// [`Code::Timer`].
+    pub value: usize,
+}
+
+impl SignalInfo {
+    /// Returns `None` if the kernel gave us a signal number we don't
+    /// recognise.
+    pub fn from_siginfo(info: &libc::siginfo_t) -> Option<SignalInfo> {
+        let signal = Signal::from_raw(info.si_signo)?;
+        let code = Code::new(signal, info.si_code);
+        let (pid, uid, value) = unsafe {
+            (
+                info.si_pid(),
+                info.si_uid(),
+                info.si_value().sival_ptr as usize,
+            )
+        };
+        Some(SignalInfo {
+            signal,
+            code,
+            pid,
+            uid,
+            value,
+        })
+    }
+
+    pub fn from_signalfd(info: &libc::signalfd_siginfo) -> Option<SignalInfo> {
+        let signal = Signal::from_raw(info.ssi_signo as c_int)?;
+        Some(SignalInfo {
+            signal,
+            code: Code::new(signal, info.ssi_code),
+            pid: info.ssi_pid as pid_t,
+            uid: info.ssi_uid as uid_t,
+            value: info.ssi_ptr as usize,
+        })
+    }
+
+    /// The payload as an integer, for signals sent with
+    /// [`realtime::queue_int`](crate::realtime::queue_int). On
+    /// little-endian targets `sival_int` shares its storage with the low bits
+    /// of `sival_ptr`.
+    pub fn int_value(&self) -> i32 {
+        self.value as i32
+    }
+
+    /// The `(pid, uid)` of whoever sent the signal, if it was sent by a
+    /// process.
+    pub fn sender(&self) -> Option<(pid_t, uid_t)> {
+        if self.code.has_sender() {
+            Some((self.pid, self.uid))
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,385 @@
+//! A typed signal number, with names, descriptions and default actions for
+//! every signal Linux knows about.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
+
+use libc::c_int;
+
+extern "C" {
+    // glibc keeps a couple of real-time signals for itself, so these aren't
+    // constants: we have to ask.
+    fn __libc_current_sigrtmin() -> c_int;
+    fn __libc_current_sigrtmax() -> c_int;
+}
+
+/// The kernel's idea of where the real-time signals start, before glibc
+/// takes its share.
+const SIGRTMIN_KERNEL: c_int = 32;
+
+/// Signal numbers run from 1 up to (and including) `SIGRTMAX`, which is 64
+/// on Linux.
+const NSIG: c_int = 65;
+
+/// What happens to a process that receives a signal it hasn't done anything
+/// about. See the table in `man 7 signal`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DefaultAction {
+    Terminate,
+    /// Terminate and dump core.
+    Core,
+    Ignore,
+    Stop,
+    /// Continue if stopped.
+    Continue,
+}
+
+use DefaultAction::*;
+
+/// Name, `strsignal`-style description and default action for each of the
+/// standard signals, indexed by signal number minus one.
+const STANDARD: [(&str, &str, DefaultAction); 31] = [
+    ("SIGHUP", "Hangup", Terminate),
+    ("SIGINT", "Interrupt", Terminate),
+    ("SIGQUIT", "Quit", Core),
+    ("SIGILL", "Illegal instruction", Core),
+    ("SIGTRAP", "Trace/breakpoint trap", Core),
+    ("SIGABRT", "Aborted", Core),
+    ("SIGBUS", "Bus error", Core),
+    ("SIGFPE", "Floating point exception", Core),
+    ("SIGKILL", "Killed", Terminate),
+    ("SIGUSR1", "User defined signal 1", Terminate),
+    ("SIGSEGV", "Segmentation fault", Core),
+    ("SIGUSR2", "User defined signal 2", Terminate),
+    ("SIGPIPE", "Broken pipe", Terminate),
+    ("SIGALRM", "Alarm clock", Terminate),
+    ("SIGTERM", "Terminated", Terminate),
+    ("SIGSTKFLT", "Stack fault", Terminate),
+    ("SIGCHLD", "Child exited", Ignore),
+    ("SIGCONT", "Continued", Continue),
+    ("SIGSTOP", "Stopped (signal)", Stop),
+    ("SIGTSTP", "Stopped", Stop),
+    ("SIGTTIN", "Stopped (tty input)", Stop),
+    ("SIGTTOU", "Stopped (tty output)", Stop),
+    ("SIGURG", "Urgent I/O condition", Ignore),
+    ("SIGXCPU", "CPU time limit exceeded", Core),
+    ("SIGXFSZ", "File size limit exceeded", Core),
+    ("SIGVTALRM", "Virtual timer expired", Terminate),
+    ("SIGPROF", "Profiling timer expired", Terminate),
+    ("SIGWINCH", "Window changed", Ignore),
+    ("SIGIO", "I/O possible", Terminate),
+    ("SIGPWR", "Power failure", Terminate),
+    ("SIGSYS", "Bad system call", Core),
+];
+
+/// Other names that some signals go by.
+const ALIASES: [(&str, c_int); 3] = [
+    ("SIGIOT", libc::SIGABRT),
+    ("SIGPOLL", libc::SIGIO),
+    ("SIGCLD", libc::SIGCHLD),
+];
+
+/// A signal number, as understood by the kernel.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Signal(c_int);
+
+impl Signal {
+    pub const HUP: Signal = Signal(libc::SIGHUP);
+    pub const INT: Signal = Signal(libc::SIGINT);
+    pub const QUIT: Signal = Signal(libc::SIGQUIT);
+    pub const ILL: Signal = Signal(libc::SIGILL);
+    pub const TRAP: Signal = Signal(libc::SIGTRAP);
+    pub const ABRT: Signal = Signal(libc::SIGABRT);
+    pub const BUS: Signal = Signal(libc::SIGBUS);
+    pub const FPE: Signal = Signal(libc::SIGFPE);
+    pub const KILL: Signal = Signal(libc::SIGKILL);
+    pub const USR1: Signal = Signal(libc::SIGUSR1);
+    pub const SEGV: Signal = Signal(libc::SIGSEGV);
+    pub const USR2: Signal = Signal(libc::SIGUSR2);
+    pub const PIPE: Signal = Signal(libc::SIGPIPE);
+    pub const ALRM: Signal = Signal(libc::SIGALRM);
+    pub const TERM: Signal = Signal(libc::SIGTERM);
+    pub const STKFLT: Signal = Signal(libc::SIGSTKFLT);
+    pub const CHLD: Signal = Signal(libc::SIGCHLD);
+    pub const CONT: Signal = Signal(libc::SIGCONT);
+    pub const STOP: Signal = Signal(libc::SIGSTOP);
+    pub const TSTP: Signal = Signal(libc::SIGTSTP);
+    pub const TTIN: Signal = Signal(libc::SIGTTIN);
+    pub const TTOU: Signal = Signal(libc::SIGTTOU);
+    pub const URG: Signal = Signal(libc::SIGURG);
+    pub const XCPU: Signal = Signal(libc::SIGXCPU);
+    pub const XFSZ: Signal = Signal(libc::SIGXFSZ);
+    pub const VTALRM: Signal = Signal(libc::SIGVTALRM);
+    pub const PROF: Signal = Signal(libc::SIGPROF);
+    pub const WINCH: Signal = Signal(libc::SIGWINCH);
+    pub const IO: Signal = Signal(libc::SIGIO);
+    pub const PWR: Signal = Signal(libc::SIGPWR);
+    pub const SYS: Signal = Signal(libc::SIGSYS);
+
+    /// Returns `None` if `signo` isn't a signal number the kernel knows about.
+    pub fn from_raw(signo: c_int) -> Option<Signal> {
+        if signo > 0 && signo < NSIG {
+            Some(Signal(signo))
+        } else {
+            None
+        }
+    }
+
+    /// Every signal number, standard and real-time, in order.
+    pub fn all() -> impl Iterator<Item = Signal> {
+        (1..NSIG).map(Signal)
+    }
+
+    /// The lowest real-time signal that's available to applications.
+    pub fn rt_min() -> Signal {
+        Signal(unsafe { __libc_current_sigrtmin() })
+    }
+
+    /// The highest real-time signal.
+    pub fn rt_max() -> Signal {
+        Signal(unsafe { __libc_current_sigrtmax() })
+    }
+
+    /// The real-time signal `offset` places above `rt_min`, if there is one.
+    pub fn rt(offset: c_int) -> Option<Signal> {
+        let signo = Signal::rt_min().0.checked_add(offset)?;
+        if offset >= 0 && signo <= Signal::rt_max().0 {
+            Some(Signal(signo))
+        } else {
+            None
+        }
+    }
+
+    /// Real-time signals are queued: send one five times and it's delivered
+    /// five times. Standard signals are merged while they're pending.
+    pub fn is_realtime(self) -> bool {
+        self.0 >= SIGRTMIN_KERNEL && self.0 < NSIG
+    }
+
+    pub const fn as_raw(self) -> c_int {
+        self.0
+    }
+
+    /// The `SIG...` name, for the standard signals. Real-time signals don't
+    /// have a fixed name; `Display` shows them relative to `SIGRTMIN`.
+    pub fn name(self) -> Option<&'static str> {
+        self.standard().map(|(name, _, _)| name)
+    }
+
+    /// What `Display` writes, split into a fixed part and an optional number
+    /// to follow it, so that it can be written out without allocating (from
+    /// a signal handler, say).
+    pub(crate) fn label(self) -> (&'static str, Option<c_int>) {
+        if let Some(name) = self.name() {
+            return (name, None);
+        }
+        let (min, max) = (Signal::rt_min().0, Signal::rt_max().0);
+        if self.0 == min {
+            ("SIGRTMIN", None)
+        } else if self.0 == max {
+            ("SIGRTMAX", None)
+        } else if self.0 > min && self.0 < max {
+            ("SIGRTMIN+", Some(self.0 - min))
+        } else {
+            // The real-time signals that glibc keeps for itself.
+            ("SIG", Some(self.0))
+        }
+    }
+
+    /// A human-readable description, worded the way `strsignal(3)` would.
+    pub fn description(self) -> Cow<'static, str> {
+        match self.standard() {
+            Some((_, description, _)) => description.into(),
+            // glibc doesn't admit to the ones it has kept for itself.
+            None if self.0 < Signal::rt_min().0 => format!("Unknown signal {}", self.0).into(),
+            None => format!("Real-time signal {}", self.0 - Signal::rt_min().0).into(),
+        }
+    }
+
+    /// What happens if nobody handles, blocks or ignores this signal.
+    pub fn default_action(self) -> DefaultAction {
+        self.standard().map_or(Terminate, |(_, _, action)| action)
+    }
+
+    fn standard(self) -> Option<(&'static str, &'static str, DefaultAction)> {
+        if self.0 < SIGRTMIN_KERNEL {
+            Some(STANDARD[self.0 as usize - 1])
+        } else {
+            None
+        }
+    }
+
+    /// `SIGKILL` and `SIGSTOP` can't be caught, blocked or ignored.
+    pub const fn is_catchable(self) -> bool {
+        self.0 != libc::SIGKILL && self.0 != libc::SIGSTOP
+    }
+
+    /// Returns `self`, or refuses to compile if used in a const context with
+    /// a signal that can't be caught:
+    ///
+    /// ```compile_fail
+    /// use example::signal::Signal;
+    ///
+    /// const SHUTDOWN: Signal = Signal::KILL.catchable();
+    /// ```
+    ///
+    /// Everything that installs a handler also checks at runtime, and fails
+    /// with [`Error::Uncatchable`](crate::sigaction::Error::Uncatchable).
+    pub const fn catchable(self) -> Signal {
+        if !self.is_catchable() {
+            panic!("SIGKILL and SIGSTOP cannot be caught, blocked or ignored");
+        }
+        self
+    }
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.label() {
+            (name, None) => f.write_str(name),
+            (prefix, Some(n)) => write!(f, "{}{}", prefix, n),
+        }
+    }
+}
+
+impl fmt::Debug for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}({})", self, self.0)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseSignalError(String);
+
+impl fmt::Display for ParseSignalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "not a signal: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for ParseSignalError {}
+
+impl FromStr for Signal {
+    type Err = ParseSignalError;
+
+    /// Accepts numbers (`"2"`), and names with or without the `SIG` prefix,
+    /// in any case (`"SIGINT"`, `"INT"`, `"int"`). Real-time signals can be
+    /// given relative to either end of the range (`"SIGRTMIN+3"`,
+    /// `"RTMAX-1"`).
+    fn from_str(s: &str) -> Result<Signal, ParseSignalError> {
+        let error = || ParseSignalError(s.to_owned());
+        if let Ok(signo) = s.parse() {
+            return Signal::from_raw(signo).ok_or_else(error);
+        }
+
+        let upper = s.trim().to_ascii_uppercase();
+        let name = if upper.starts_with("SIG") {
+            upper
+        } else {
+            format!("SIG{}", upper)
+        };
+
+        if let Some(i) = STANDARD.iter().position(|(n, _, _)| *n == name) {
+            return Ok(Signal(i as c_int + 1));
+        }
+        if let Some((_, signo)) = ALIASES.iter().find(|(n, _)| *n == name) {
+            return Ok(Signal(*signo));
+        }
+        // How `Display` shows the real-time signals that glibc reserves.
+        if let Some(signo) = name.strip_prefix("SIG").and_then(parse_digits) {
+            return Signal::from_raw(signo).ok_or_else(error);
+        }
+
+        let (base, rest, sign): (c_int, &str, c_int) =
+            if let Some(rest) = name.strip_prefix("SIGRTMIN") {
+                (Signal::rt_min().0, rest, 1)
+            } else if let Some(rest) = name.strip_prefix("SIGRTMAX") {
+                (Signal::rt_max().0, rest, -1)
+            } else {
+                return Err(error());
+            };
+        let offset: c_int = match rest.strip_prefix(if sign > 0 { '+' } else { '-' }) {
+            _ if rest.is_empty() => 0,
+            Some(n) => parse_digits(n).ok_or_else(error)?,
+            None => return Err(error()),
+        };
+        let signo = sign
+            .checked_mul(offset)
+            .and_then(|offset| base.checked_add(offset))
+            .ok_or_else(error)?;
+        if signo >= Signal::rt_min().0 && signo <= Signal::rt_max().0 {
+            Ok(Signal(signo))
+        } else {
+            Err(error())
+        }
+    }
+}
+
+/// Parses a plain run of digits, without the sign or whitespace that
+/// `str::parse` would let through.
+fn parse_digits(s: &str) -> Option<c_int> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_str() {
+        let (min, max) = (Signal::rt_min().0, Signal::rt_max().0);
+        let cases = [
+            ("2", Some(libc::SIGINT)),
+            ("SIGINT", Some(libc::SIGINT)),
+            ("int", Some(libc::SIGINT)),
+            (" sigterm ", Some(libc::SIGTERM)),
+            ("SIGCLD", Some(libc::SIGCHLD)),
+            ("SIG33", Some(33)),
+            ("SIGRTMIN", Some(min)),
+            ("RTMIN+2", Some(min + 2)),
+            ("SIGRTMAX", Some(max)),
+            ("rtmax-1", Some(max - 1)),
+            ("0", None),
+            ("65", None),
+            ("SIG", None),
+            ("SIGFOO", None),
+            ("SIG+2", None),
+            ("RTMIN-1", None),
+            ("RTMIN+-1", None),
+            ("RTMIN+99", None),
+            ("RTMAX+1", None),
+            ("RTMIN+2147483647", None),
+            ("RTMAX-2147483647", None),
+        ];
+        for (input, expected) in cases.iter() {
+            let parsed = input.parse::<Signal>().ok().map(Signal::as_raw);
+            assert_eq!(parsed, *expected, "parsing {:?}", input);
+        }
+    }
+
+    #[test]
+    fn display() {
+        let min = Signal::rt_min().0;
+        let cases = [
+            (libc::SIGINT, "SIGINT"),
+            (libc::SIGCHLD, "SIGCHLD"),
+            (min, "SIGRTMIN"),
+            (min + 3, "SIGRTMIN+3"),
+            (Signal::rt_max().0, "SIGRTMAX"),
+        ];
+        for (signo, expected) in cases.iter() {
+            assert_eq!(Signal(*signo).to_string(), *expected);
+        }
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for signal in Signal::all() {
+            assert_eq!(signal.to_string().parse(), Ok(signal));
+        }
+    }
+}
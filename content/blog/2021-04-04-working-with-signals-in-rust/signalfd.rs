@@ -0,0 +1,156 @@
+//! Receiving signals through `signalfd(2)` instead of a handler.
+//!
+//! With a signalfd there's no handler at all: the signals are blocked, so they
+//! stay pending, and the kernel hands them out as `signalfd_siginfo` records
+//! when the fd is read. That gets rid of the async-signal-safety problem
+//! entirely, and the fd can go straight into an `epoll` loop.
+//!
+//! [signalfd is useless](https://ldpreload.com/blog/signalfd-is-useless)
+//! covers the ways this goes wrong. The two we deal with here are:
+//!
+//! * the signals have to be blocked in _every_ thread, or the kernel will
+//!   deliver them to whichever thread has them unblocked (running the default
+//!   action, which for most signals is to kill the process);
+//! * the blocked mask is inherited across `fork` and `exec`, so children
+//!   start life unable to receive the very signals we were interested in.
+//!   Use [`CommandExt::restore_signal_mask`] when spawning them.
+
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::process::CommandExt as _;
+use std::process::Command;
+use std::time::Duration;
+
+use crate::fd;
+use crate::mask;
+use crate::sigaction::Error;
+use crate::signal::Signal;
+use crate::sigset::SigSet;
+
+/// A signalfd, with its signals blocked in every thread. Dropping it unblocks
+/// them again, unless something else still needs them blocked, at which
+/// point anything still pending is delivered as normal.
+#[derive(Debug)]
+pub struct SignalFd {
+    fd: OwnedFd,
+    signals: Vec<Signal>,
+}
+
+impl SignalFd {
+    /// Blocks `signals` in every thread and opens a signalfd for them.
+    ///
+    /// `SIGRTMAX` is reserved for changing other threads' masks (see
+    /// [`mask`](crate::mask)), so asking for it gives [`Error::Registered`],
+    /// and a thread that has it blocked gives [`Error::Mask`].
+    pub fn new(signals: &[Signal]) -> Result<SignalFd, Error> {
+        if let Some(&signal) = signals.iter().find(|s| !s.is_catchable()) {
+            return Err(Error::Uncatchable(signal));
+        }
+        let wanted = SigSet::from(signals);
+
+        mask::block(wanted)?;
+
+        let set = wanted.to_sigset();
+        let raw = unsafe { libc::signalfd(-1, &set, libc::SFD_NONBLOCK | libc::SFD_CLOEXEC) };
+        if raw < 0 {
+            let e = io::Error::last_os_error();
+            let _ = mask::release(wanted);
+            return Err(e.into());
+        }
+        Ok(SignalFd {
+            fd: unsafe { OwnedFd::from_raw_fd(raw) },
+            signals: signals.to_vec(),
+        })
+    }
+
+    /// Reads the next pending signal, if there is one.
+    pub fn try_read(&self) -> io::Result<Option<libc::signalfd_siginfo>> {
+        loop {
+            let mut info: libc::signalfd_siginfo = unsafe { mem::zeroed() };
+            let n = unsafe {
+                libc::read(
+                    self.fd.as_raw_fd(),
+                    &mut info as *mut _ as *mut libc::c_void,
+                    mem::size_of::<libc::signalfd_siginfo>(),
+                )
+            };
+            if n < 0 {
+                let e = io::Error::last_os_error();
+                match e.kind() {
+                    io::ErrorKind::Interrupted => continue,
+                    io::ErrorKind::WouldBlock => return Ok(None),
+                    _ => return Err(e),
+                }
+            }
+            // The kernel only ever hands out whole records.
+            debug_assert_eq!(n as usize, mem::size_of::<libc::signalfd_siginfo>());
+            return Ok(Some(info));
+        }
+    }
+
+    /// Blocks until a signal is pending, then reads it.
+    pub fn read(&self) -> io::Result<libc::signalfd_siginfo> {
+        loop {
+            if let Some(info) = self.try_read()? {
+                return Ok(info);
+            }
+            fd::wait_readable(self.fd.as_raw_fd(), None)?;
+        }
+    }
+
+    /// Like [`SignalFd::read`], but gives up after `timeout`.
+    pub fn read_timeout(&self, timeout: Duration) -> io::Result<Option<libc::signalfd_siginfo>> {
+        let deadline = fd::deadline(timeout);
+        loop {
+            if let Some(info) = self.try_read()? {
+                return Ok(Some(info));
+            }
+            if fd::expired(deadline) {
+                return Ok(None);
+            }
+            fd::wait_readable(self.fd.as_raw_fd(), fd::remaining(deadline))?;
+        }
+    }
+
+    pub fn signals(&self) -> &[Signal] {
+        &self.signals
+    }
+}
+
+impl AsRawFd for SignalFd {
+    /// The fd is non-blocking; wait for it to become readable and then use
+    /// [`SignalFd::try_read`].
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl Drop for SignalFd {
+    fn drop(&mut self) {
+        let _ = mask::release(SigSet::from(&self.signals[..]));
+    }
+}
+
+/// Lets children spawned with `std::process::Command` start with the signal
+/// mask this process inherited, rather than one with the signals blocked
+/// for a signalfd or a [`Waiter`](crate::waiter::Waiter).
+pub trait CommandExt {
+    fn restore_signal_mask(&mut self) -> &mut Command;
+}
+
+impl CommandExt for Command {
+    fn restore_signal_mask(&mut self) -> &mut Command {
+        let set = mask::inherited().unwrap_or_default().to_sigset();
+        // Runs between fork and exec, so it has to stick to async-signal-safe
+        // calls; pthread_sigmask is one of those.
+        unsafe {
+            self.pre_exec(move || {
+                match libc::pthread_sigmask(libc::SIG_SETMASK, &set, std::ptr::null_mut()) {
+                    0 => Ok(()),
+                    e => Err(io::Error::from_raw_os_error(e)),
+                }
+            })
+        }
+    }
+}
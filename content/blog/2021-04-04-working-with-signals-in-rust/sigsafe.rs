@@ -0,0 +1,181 @@
+//! Output that's safe to produce from inside a signal handler.
+//!
+//! `println!` takes the stdout lock and may allocate, neither of which is
+//! allowed in a handler: if the signal arrived while the interrupted code was
+//! holding the lock, we'd deadlock. `write(2)` _is_ async-signal-safe, so
+//! here we format into a buffer on the stack and hand that straight to the
+//! kernel.
+//!
+//! ```no_run
+//! use example::sigsafe::Line;
+//!
+//! extern "C" fn handle_interrupt(sig: libc::c_int) {
+//!     Line::new()
+//!         .str("Sorry we didn't get the chance to finish (got ")
+//!         .signo(sig)
+//!         .str(")\n")
+//!         .write_stdout();
+//! }
+//! ```
+
+use std::os::unix::io::RawFd;
+
+use libc::c_int;
+
+use crate::fd;
+use crate::signal::Signal;
+
+const CAPACITY: usize = 256;
+
+/// A line of output, built up on the stack. Anything that doesn't fit is
+/// quietly dropped: there's nobody to report an error to.
+pub struct Line {
+    buf: [u8; CAPACITY],
+    len: usize,
+}
+
+impl Default for Line {
+    fn default() -> Line {
+        Line::new()
+    }
+}
+
+impl Line {
+    pub const fn new() -> Line {
+        Line {
+            buf: [0; CAPACITY],
+            len: 0,
+        }
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) -> &mut Line {
+        let n = bytes.len().min(CAPACITY - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&bytes[..n]);
+        self.len += n;
+        self
+    }
+
+    pub fn str(&mut self, s: &str) -> &mut Line {
+        self.bytes(s.as_bytes())
+    }
+
+    pub fn uint(&mut self, mut n: u64) -> &mut Line {
+        // u64::MAX has 20 digits.
+        let mut digits = [0u8; 20];
+        let mut i = digits.len();
+        loop {
+            i -= 1;
+            digits[i] = b'0' + (n % 10) as u8;
+            n /= 10;
+            if n == 0 {
+                break;
+            }
+        }
+        self.bytes(&digits[i..])
+    }
+
+    pub fn int(&mut self, n: i64) -> &mut Line {
+        if n < 0 {
+            self.bytes(b"-");
+        }
+        self.uint(n.unsigned_abs())
+    }
+
+    /// Writes the signal's name, the same way `Display` would.
+    pub fn signal(&mut self, signal: Signal) -> &mut Line {
+        match signal.label() {
+            (name, None) => self.str(name),
+            (prefix, Some(n)) => self.str(prefix).int(n.into()),
+        }
+    }
+
+    /// Like [`Line::signal`], but for the raw number a handler is given.
+    pub fn signo(&mut self, signo: c_int) -> &mut Line {
+        match Signal::from_raw(signo) {
+            Some(signal) => self.signal(signal),
+            None => self.str("signal ").int(signo.into()),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    /// Writes the line to `fd`, leaving `errno` as it was.
+    pub fn write_to(&self, fd: RawFd) {
+        write_all(fd, self.as_bytes());
+    }
+
+    pub fn write_stdout(&self) {
+        self.write_to(libc::STDOUT_FILENO);
+    }
+
+    pub fn write_stderr(&self) {
+        self.write_to(libc::STDERR_FILENO);
+    }
+}
+
+/// Writes all of `bytes` to `fd`, retrying short writes and `EINTR`, and
+/// giving up silently on any other error. `errno` is preserved, since the
+/// code we've interrupted may be about to look at it.
+pub fn write_all(fd: RawFd, mut bytes: &[u8]) {
+    fd::preserving_errno(|| {
+        while !bytes.is_empty() {
+            let n = unsafe { libc::write(fd, bytes.as_ptr() as *const libc::c_void, bytes.len()) };
+            if n < 0 {
+                if unsafe { *libc::__errno_location() } == libc::EINTR {
+                    continue;
+                }
+                break;
+            }
+            bytes = &bytes[n as usize..];
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(line: &Line) -> &str {
+        std::str::from_utf8(line.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(text(Line::new().uint(0)), "0");
+        assert_eq!(text(Line::new().uint(u64::MAX)), "18446744073709551615");
+        assert_eq!(text(Line::new().int(0)), "0");
+        assert_eq!(text(Line::new().int(-42)), "-42");
+        assert_eq!(text(Line::new().int(i64::MIN)), "-9223372036854775808");
+    }
+
+    #[test]
+    fn signal_matches_display() {
+        let rt = Signal::from_raw(Signal::rt_min().as_raw() + 3).unwrap();
+        assert_eq!(text(Line::new().signal(rt)), "SIGRTMIN+3");
+        for signal in Signal::all() {
+            assert_eq!(text(Line::new().signal(signal)), signal.to_string());
+        }
+    }
+
+    #[test]
+    fn signo_out_of_range() {
+        assert_eq!(text(Line::new().signo(libc::SIGINT)), "SIGINT");
+        for &signo in [0, -1, 65, c_int::MAX].iter() {
+            let expected = format!("signal {}", signo);
+            assert_eq!(text(Line::new().signo(signo)), expected);
+        }
+    }
+
+    #[test]
+    fn truncates_at_capacity() {
+        let mut line = Line::new();
+        line.bytes(&[b'x'; CAPACITY - 2]).uint(12345).str("more");
+        assert_eq!(line.as_bytes().len(), CAPACITY);
+        assert!(text(&line).ends_with("xx12"));
+
+        line.str("ignored");
+        assert_eq!(line.as_bytes().len(), CAPACITY);
+    }
+}
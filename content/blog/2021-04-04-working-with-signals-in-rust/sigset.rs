@@ -0,0 +1,298 @@
+//! Sets of signals.
+//!
+//! `sigset_t` is a 1024-bit structure on Linux, of which only the first 64
+//! bits mean anything, and it can only be changed through `sigaddset` and
+//! friends. `SigSet` is those 64 bits, as a value that can be copied, stored
+//! in a `const`, and combined with the usual set operators.
+
+use std::fmt;
+use std::io;
+use std::iter::FromIterator;
+use std::mem;
+use std::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, Not, Sub, SubAssign};
+
+use crate::mask;
+use crate::signal::Signal;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct SigSet(u64);
+
+const fn bit(signal: Signal) -> u64 {
+    1 << (signal.as_raw() - 1)
+}
+
+impl SigSet {
+    pub const fn empty() -> SigSet {
+        SigSet(0)
+    }
+
+    /// Every signal, standard and real-time. `SIGKILL` and `SIGSTOP` are
+    /// included, though the kernel quietly refuses to block them.
+    pub const fn all() -> SigSet {
+        SigSet(u64::MAX)
+    }
+
+    /// Bit `n - 1` stands for signal `n`.
+    pub const fn from_bits(bits: u64) -> SigSet {
+        SigSet(bits)
+    }
+
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    /// `self` with `signal` added, for building sets in a `const`.
+    pub const fn with(self, signal: Signal) -> SigSet {
+        SigSet(self.0 | bit(signal))
+    }
+
+    pub const fn contains(self, signal: Signal) -> bool {
+        self.0 & bit(signal) != 0
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub const fn len(self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    /// Adds `signal`, returning whether it wasn't already there.
+    pub fn insert(&mut self, signal: Signal) -> bool {
+        let added = !self.contains(signal);
+        self.0 |= bit(signal);
+        added
+    }
+
+    /// Removes `signal`, returning whether it was there.
+    pub fn remove(&mut self, signal: Signal) -> bool {
+        let removed = self.contains(signal);
+        self.0 &= !bit(signal);
+        removed
+    }
+
+    /// The signals in the set, lowest first.
+    pub fn iter(self) -> impl Iterator<Item = Signal> {
+        Signal::all().filter(move |&signal| self.contains(signal))
+    }
+
+    /// The lowest signal in the set.
+    pub fn first(self) -> Option<Signal> {
+        Signal::from_raw(self.0.trailing_zeros() as libc::c_int + 1)
+    }
+
+    /// The signals glibc keeps for itself (the ones between `SIGSYS` and
+    /// [`Signal::rt_min`]) are left out: `sigaddset` refuses them.
+    pub fn to_sigset(self) -> libc::sigset_t {
+        let mut set: libc::sigset_t = unsafe { mem::zeroed() };
+        unsafe { libc::sigemptyset(&mut set) };
+        for signal in self.iter() {
+            unsafe { libc::sigaddset(&mut set, signal.as_raw()) };
+        }
+        set
+    }
+
+    pub fn from_sigset(set: &libc::sigset_t) -> SigSet {
+        Signal::all()
+            .filter(|signal| unsafe { libc::sigismember(set, signal.as_raw()) } == 1)
+            .collect()
+    }
+
+    /// The calling thread's signal mask.
+    pub fn blocked() -> io::Result<SigSet> {
+        mask::pthread_sigmask(libc::SIG_BLOCK, None)
+    }
+
+    /// The signals that are pending for the calling thread or for the
+    /// process as a whole, waiting to be unblocked.
+    pub fn pending() -> io::Result<SigSet> {
+        let mut set: libc::sigset_t = unsafe { mem::zeroed() };
+        if unsafe { libc::sigpending(&mut set) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(SigSet::from_sigset(&set))
+    }
+}
+
+impl fmt::Debug for SigSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+impl From<Signal> for SigSet {
+    fn from(signal: Signal) -> SigSet {
+        SigSet::empty().with(signal)
+    }
+}
+
+impl From<&[Signal]> for SigSet {
+    fn from(signals: &[Signal]) -> SigSet {
+        signals.iter().copied().collect()
+    }
+}
+
+impl<const N: usize> From<&[Signal; N]> for SigSet {
+    fn from(signals: &[Signal; N]) -> SigSet {
+        signals.iter().copied().collect()
+    }
+}
+
+impl FromIterator<Signal> for SigSet {
+    fn from_iter<I: IntoIterator<Item = Signal>>(signals: I) -> SigSet {
+        let mut set = SigSet::empty();
+        set.extend(signals);
+        set
+    }
+}
+
+impl Extend<Signal> for SigSet {
+    fn extend<I: IntoIterator<Item = Signal>>(&mut self, signals: I) {
+        for signal in signals {
+            self.insert(signal);
+        }
+    }
+}
+
+impl BitOr for SigSet {
+    type Output = SigSet;
+
+    fn bitor(self, rhs: SigSet) -> SigSet {
+        SigSet(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for SigSet {
+    fn bitor_assign(&mut self, rhs: SigSet) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl BitAnd for SigSet {
+    type Output = SigSet;
+
+    fn bitand(self, rhs: SigSet) -> SigSet {
+        SigSet(self.0 & rhs.0)
+    }
+}
+
+impl BitAndAssign for SigSet {
+    fn bitand_assign(&mut self, rhs: SigSet) {
+        self.0 &= rhs.0;
+    }
+}
+
+impl Sub for SigSet {
+    type Output = SigSet;
+
+    fn sub(self, rhs: SigSet) -> SigSet {
+        SigSet(self.0 & !rhs.0)
+    }
+}
+
+impl SubAssign for SigSet {
+    fn sub_assign(&mut self, rhs: SigSet) {
+        self.0 &= !rhs.0;
+    }
+}
+
+impl Not for SigSet {
+    type Output = SigSet;
+
+    fn not(self) -> SigSet {
+        SigSet(!self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signal(signo: libc::c_int) -> Signal {
+        Signal::from_raw(signo).unwrap()
+    }
+
+    fn set(signos: &[libc::c_int]) -> SigSet {
+        signos.iter().copied().map(signal).collect()
+    }
+
+    #[test]
+    fn operators() {
+        let (a, b) = (set(&[1, 2, 3]), set(&[3, 4]));
+        assert_eq!(a | b, set(&[1, 2, 3, 4]));
+        assert_eq!(a & b, set(&[3]));
+        assert_eq!(a - b, set(&[1, 2]));
+        assert_eq!(!a, SigSet::all() - a);
+        assert_eq!(!SigSet::empty(), SigSet::all());
+
+        let mut c = a;
+        c |= b;
+        assert_eq!(c, a | b);
+        c &= b;
+        assert_eq!(c, b);
+        c -= set(&[4]);
+        assert_eq!(c, set(&[3]));
+    }
+
+    #[test]
+    fn insert_and_remove() {
+        let mut s = SigSet::empty();
+        assert!(s.insert(signal(10)));
+        assert!(!s.insert(signal(10)));
+        assert_eq!(s.len(), 1);
+        assert!(s.remove(signal(10)));
+        assert!(!s.remove(signal(10)));
+        assert!(s.is_empty());
+    }
+
+    #[test]
+    fn first() {
+        assert_eq!(SigSet::empty().first(), None);
+        assert_eq!(set(&[1, 64]).first(), Some(signal(1)));
+        assert_eq!(set(&[12, 10]).first(), Some(signal(10)));
+        assert_eq!(set(&[64]).first(), Some(signal(64)));
+    }
+
+    #[test]
+    fn boundaries() {
+        let (lowest, highest) = (signal(1), Signal::all().last().unwrap());
+        assert_eq!(highest.as_raw(), 64);
+        assert_eq!(SigSet::from(lowest).bits(), 1);
+        assert_eq!(SigSet::from(highest).bits(), 1 << 63);
+        assert_eq!(SigSet::all().len(), 64);
+        assert_eq!(
+            SigSet::all().iter().collect::<Vec<_>>(),
+            Signal::all().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn sigset_t_round_trips() {
+        let reserved: SigSet = (libc::SIGSYS + 1..Signal::rt_min().as_raw())
+            .map(signal)
+            .collect();
+        let cases = [
+            SigSet::empty(),
+            set(&[1]),
+            set(&[64]),
+            set(&[libc::SIGINT, libc::SIGTERM, 34, 64]),
+            SigSet::all(),
+        ];
+        for &case in cases.iter() {
+            let raw = case.to_sigset();
+            let expected = case - reserved;
+            for signal in Signal::all() {
+                let member = unsafe { libc::sigismember(&raw, signal.as_raw()) } == 1;
+                assert_eq!(
+                    member,
+                    expected.contains(signal),
+                    "{} in {:?}",
+                    signal,
+                    case
+                );
+            }
+            assert_eq!(SigSet::from_sigset(&raw), expected);
+        }
+    }
+}
@@ -0,0 +1,55 @@
+//! A sleep that a signal can cut short.
+//!
+//! `std::thread::sleep` goes back to sleep when it's interrupted, which is
+//! exactly right for a general-purpose sleep and exactly wrong when the point
+//! of the signal was to get us to stop waiting.
+
+use std::io;
+use std::ptr;
+use std::time::Duration;
+
+use crate::fd;
+use crate::signal::Signal;
+use crate::source::Source;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sleep {
+    /// We slept for the whole duration.
+    Completed,
+    /// `signal` arrived with `remaining` still left to go.
+    Interrupted { signal: Signal, remaining: Duration },
+}
+
+/// Sleeps for `duration`, or until a signal arrives on `signals`.
+///
+/// A signal that arrived before we started counts: we return straight away
+/// with the full duration remaining.
+pub fn interruptible_sleep<S: Source>(duration: Duration, signals: &S) -> io::Result<Sleep> {
+    // Too far off for an Instant is as good as forever: ppoll without a
+    // timeout, and count the whole duration as remaining if we're woken.
+    let deadline = fd::deadline(duration);
+    loop {
+        let remaining = fd::remaining(deadline);
+        if let Some(signal) = signals.try_next()? {
+            let remaining = remaining.unwrap_or(duration);
+            return Ok(Sleep::Interrupted { signal, remaining });
+        }
+        if remaining == Some(Duration::from_secs(0)) {
+            return Ok(Sleep::Completed);
+        }
+
+        let mut pollfd = libc::pollfd {
+            fd: signals.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = remaining.map(fd::timespec);
+        let timeout = timeout.as_ref().map_or(ptr::null(), |t| t as *const _);
+        if unsafe { libc::ppoll(&mut pollfd, 1, timeout, ptr::null()) } < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+    }
+}
@@ -0,0 +1,39 @@
+//! Anything that signals can be read from.
+
+use std::io;
+use std::os::unix::io::AsRawFd;
+
+use crate::pipe;
+use crate::siginfo::SignalInfo;
+use crate::signal::Signal;
+use crate::signalfd::SignalFd;
+
+/// A non-blocking fd that becomes readable when a signal arrives, along with
+/// a way to pull that signal out once it has.
+///
+/// This is what lets the waiting primitives in this crate work the same way
+/// on top of a self-pipe or a signalfd.
+pub trait Source: AsRawFd {
+    /// Returns the next signal if one has already arrived, without blocking.
+    fn try_next(&self) -> io::Result<Option<Signal>>;
+}
+
+impl Source for pipe::Receiver<Signal> {
+    fn try_next(&self) -> io::Result<Option<Signal>> {
+        self.try_recv()
+    }
+}
+
+impl Source for pipe::Receiver<SignalInfo> {
+    fn try_next(&self) -> io::Result<Option<Signal>> {
+        Ok(self.try_recv()?.map(|info| info.signal))
+    }
+}
+
+impl Source for SignalFd {
+    fn try_next(&self) -> io::Result<Option<Signal>> {
+        Ok(self
+            .try_read()?
+            .and_then(|info| Signal::from_raw(info.ssi_signo as libc::c_int)))
+    }
+}
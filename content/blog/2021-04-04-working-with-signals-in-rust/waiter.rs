@@ -0,0 +1,143 @@
+//! Handling signals on a dedicated thread, in ordinary Rust.
+//!
+//! Rather than running anything in signal context, we block the signals in
+//! every thread and have one thread sit in `sigwaitinfo`. The kernel hands
+//! that thread each signal as it becomes pending, and from there we just call
+//! a closure. The closures run on a normal thread, at a normal time, so they
+//! can print, lock and allocate like any other code.
+//!
+//! Children inherit the blocked mask, just as they do with a
+//! [`SignalFd`](crate::signalfd::SignalFd), so spawn them with
+//! [`CommandExt::restore_signal_mask`](crate::signalfd::CommandExt::restore_signal_mask).
+
+use std::collections::HashMap;
+use std::mem;
+use std::os::unix::thread::JoinHandleExt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use crate::mask;
+use crate::sigaction::Error;
+use crate::signal::Signal;
+use crate::sigset::SigSet;
+
+type Callback = Box<dyn FnMut(Signal) + Send>;
+
+/// Collects callbacks, and then starts the thread that runs them.
+#[derive(Default)]
+pub struct Waiter {
+    callbacks: HashMap<Signal, Vec<Callback>>,
+}
+
+impl Waiter {
+    pub fn new() -> Waiter {
+        Waiter::default()
+    }
+
+    /// Calls `callback` on the waiting thread every time `signal` arrives.
+    /// Several callbacks for the same signal run in the order they were
+    /// added.
+    pub fn on<F>(mut self, signal: Signal, callback: F) -> Waiter
+    where
+        F: FnMut(Signal) + Send + 'static,
+    {
+        self.callbacks
+            .entry(signal)
+            .or_default()
+            .push(Box::new(callback));
+        self
+    }
+
+    /// Blocks every signal that has a callback, in every thread, and starts
+    /// waiting for them.
+    ///
+    /// As with a [`SignalFd`](crate::signalfd::SignalFd), `SIGRTMAX` is
+    /// reserved and gives [`Error::Registered`].
+    pub fn spawn(self) -> Result<Handle, Error> {
+        if let Some(&signal) = self.callbacks.keys().find(|s| !s.is_catchable()) {
+            return Err(Error::Uncatchable(signal));
+        }
+        let wanted: SigSet = self.callbacks.keys().copied().collect();
+        let wake = match wanted.first() {
+            Some(signal) => signal,
+            None => {
+                // sigwaitinfo with an empty set would never return, so there's
+                // no point in starting a thread to sit in it.
+                return Ok(Handle {
+                    thread: None,
+                    stop: Arc::new(AtomicBool::new(true)),
+                    wake: Signal::INT,
+                    signals: SigSet::empty(),
+                });
+            }
+        };
+        mask::block(wanted)?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = Arc::clone(&stop);
+            let mut callbacks = self.callbacks;
+            thread::Builder::new()
+                .name("signal-waiter".into())
+                .spawn(move || wait(wanted, &stop, &mut callbacks))
+        };
+        match thread {
+            Ok(thread) => Ok(Handle {
+                thread: Some(thread),
+                stop,
+                wake,
+                signals: wanted,
+            }),
+            Err(e) => {
+                let _ = mask::release(wanted);
+                Err(e.into())
+            }
+        }
+    }
+}
+
+fn wait(signals: SigSet, stop: &AtomicBool, callbacks: &mut HashMap<Signal, Vec<Callback>>) {
+    let set = signals.to_sigset();
+    while !stop.load(Ordering::Acquire) {
+        let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+        let signo = unsafe { libc::sigwaitinfo(&set, &mut info) };
+        if signo < 0 || stop.load(Ordering::Acquire) {
+            continue;
+        }
+        let signal = match Signal::from_raw(signo) {
+            Some(signal) => signal,
+            None => continue,
+        };
+        for callback in callbacks.get_mut(&signal).into_iter().flatten() {
+            // One misbehaving callback shouldn't leave the process deaf to
+            // signals for the rest of its life.
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| callback(signal)));
+        }
+    }
+}
+
+/// The running waiter thread. Dropping this stops the thread, and unblocks
+/// the signals again unless something else still needs them blocked.
+pub struct Handle {
+    thread: Option<JoinHandle<()>>,
+    stop: Arc<AtomicBool>,
+    /// One of the signals the thread is waiting for, used to wake it up when
+    /// it's time to stop.
+    wake: Signal,
+    /// The signals we had [`mask`] block, to be handed back.
+    signals: SigSet,
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            self.stop.store(true, Ordering::Release);
+            if unsafe { libc::pthread_kill(thread.as_pthread_t(), self.wake.as_raw()) } == 0 {
+                let _ = thread.join();
+            }
+        }
+        let _ = mask::release(self.signals);
+    }
+}